@@ -3,15 +3,15 @@ extern crate Archive;
 use Archive::*;
 
 fn main() {
-    let a = Reader::new().unwrap()
+    let mut a = Reader::new().unwrap()
         .support_filter_all()
         .support_format_all()
         .open_filename("archive.tar", 10240).unwrap();
 
-    loop {
-        match a.next_header() {
+    while let Some(entry) = a.next_entry() {
+        match entry {
             Ok(e) => println!("{:?}", e.pathname()),
-            Err(_) => { break }
+            Err(err) => println!("error: {}", err),
         }
     }
 
@@ -7,15 +7,15 @@ fn main() {
 
     let f = File::open("archive.tar").unwrap();
 
-    let a = Reader::new().unwrap()
+    let mut a = Reader::new().unwrap()
     .support_filter_all()
     .support_format_all()
     .open_stream(f).unwrap();
 
-    loop {
-        match a.next_header() {
+    while let Some(entry) = a.next_entry() {
+        match entry {
                 Ok(e) => println!("{:?}", e.pathname()),
-                Err(_) => { break }
+                Err(err) => println!("error: {}", err),
             }
     }
 
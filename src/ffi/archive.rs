@@ -1,7 +1,7 @@
 
 extern crate libc;
 
-pub use self::libc::{c_void, c_int, c_uint, c_char, c_long, c_ulong, ssize_t, wchar_t, size_t, time_t, FILE, stat, dev_t, mode_t};
+pub use self::libc::{c_void, c_int, c_uint, c_char, c_long, c_ulong, ssize_t, wchar_t, size_t, time_t, FILE, stat, dev_t, mode_t, SEEK_SET, SEEK_CUR, SEEK_END};
 
 // empty enums seem to be the recommended state I could find, see:
 // https://github.com/rust-lang/rust/issues/27303
@@ -38,6 +38,9 @@ pub type archive_switch_callback =
     extern "C" fn(arg1: *mut Struct_archive,
                   _client_data1: *mut c_void,
                   _client_data2: *mut c_void) -> c_int;
+pub type archive_passphrase_callback =
+    extern "C" fn(arg1: *mut Struct_archive,
+                  _client_data: *mut c_void) -> *const c_char;
 
 //pub enum archive_result {
 pub const ARCHIVE_OK:		c_int = 0;
@@ -47,6 +50,67 @@ pub const ARCHIVE_RETRY:	c_int = -10;
 pub const ARCHIVE_EOF:		c_int = 1;
 pub const ARCHIVE_FATAL:	c_int = -30;
 
+/* Error numbers set by libarchive itself. */
+pub const ARCHIVE_ERRNO_FILE_FORMAT: c_int = self::libc::EILSEQ;
+pub const ARCHIVE_ERRNO_PROGRAMMER: c_int = self::libc::EINVAL;
+pub const ARCHIVE_ERRNO_MISC: c_int = -1;
+
+/* Returned by archive_read_has_encrypted_entries() */
+pub const ARCHIVE_READ_FORMAT_ENCRYPTION_UNSUPPORTED: c_int = -2;
+pub const ARCHIVE_READ_FORMAT_ENCRYPTION_DONT_KNOW: c_int = -1;
+
+/* Codes returned by archive_filter_code. */
+pub const ARCHIVE_FILTER_NONE:		c_int = 0;
+pub const ARCHIVE_FILTER_GZIP:		c_int = 1;
+pub const ARCHIVE_FILTER_BZIP2:		c_int = 2;
+pub const ARCHIVE_FILTER_COMPRESS:	c_int = 3;
+pub const ARCHIVE_FILTER_PROGRAM:	c_int = 4;
+pub const ARCHIVE_FILTER_LZMA:		c_int = 5;
+pub const ARCHIVE_FILTER_XZ:		c_int = 6;
+pub const ARCHIVE_FILTER_UU:		c_int = 7;
+pub const ARCHIVE_FILTER_RPM:		c_int = 8;
+pub const ARCHIVE_FILTER_LZIP:		c_int = 9;
+pub const ARCHIVE_FILTER_LRZIP:		c_int = 10;
+pub const ARCHIVE_FILTER_LZOP:		c_int = 11;
+pub const ARCHIVE_FILTER_GRZIP:		c_int = 12;
+pub const ARCHIVE_FILTER_LZ4:		c_int = 13;
+pub const ARCHIVE_FILTER_ZSTD:		c_int = 14;
+
+/* Codes returned by archive_format. */
+pub const ARCHIVE_FORMAT_BASE_MASK:		c_int = 0xff0000;
+pub const ARCHIVE_FORMAT_CPIO:			c_int = 0x10000;
+pub const ARCHIVE_FORMAT_CPIO_POSIX:		c_int = ARCHIVE_FORMAT_CPIO | 1;
+pub const ARCHIVE_FORMAT_CPIO_BIN_LE:		c_int = ARCHIVE_FORMAT_CPIO | 2;
+pub const ARCHIVE_FORMAT_CPIO_BIN_BE:		c_int = ARCHIVE_FORMAT_CPIO | 3;
+pub const ARCHIVE_FORMAT_CPIO_SVR4_NOCRC:	c_int = ARCHIVE_FORMAT_CPIO | 4;
+pub const ARCHIVE_FORMAT_CPIO_SVR4_CRC:		c_int = ARCHIVE_FORMAT_CPIO | 5;
+pub const ARCHIVE_FORMAT_CPIO_AFIO_LARGE:	c_int = ARCHIVE_FORMAT_CPIO | 6;
+pub const ARCHIVE_FORMAT_CPIO_PWB:		c_int = ARCHIVE_FORMAT_CPIO | 7;
+pub const ARCHIVE_FORMAT_SHAR:			c_int = 0x20000;
+pub const ARCHIVE_FORMAT_SHAR_BASE:		c_int = ARCHIVE_FORMAT_SHAR | 1;
+pub const ARCHIVE_FORMAT_SHAR_DUMP:		c_int = ARCHIVE_FORMAT_SHAR | 2;
+pub const ARCHIVE_FORMAT_TAR:			c_int = 0x30000;
+pub const ARCHIVE_FORMAT_TAR_USTAR:		c_int = ARCHIVE_FORMAT_TAR | 1;
+pub const ARCHIVE_FORMAT_TAR_PAX_INTERCHANGE:	c_int = ARCHIVE_FORMAT_TAR | 2;
+pub const ARCHIVE_FORMAT_TAR_PAX_RESTRICTED:	c_int = ARCHIVE_FORMAT_TAR | 3;
+pub const ARCHIVE_FORMAT_TAR_GNUTAR:		c_int = ARCHIVE_FORMAT_TAR | 4;
+pub const ARCHIVE_FORMAT_ISO9660:		c_int = 0x40000;
+pub const ARCHIVE_FORMAT_ISO9660_ROCKRIDGE:	c_int = ARCHIVE_FORMAT_ISO9660 | 1;
+pub const ARCHIVE_FORMAT_ZIP:			c_int = 0x50000;
+pub const ARCHIVE_FORMAT_EMPTY:			c_int = 0x60000;
+pub const ARCHIVE_FORMAT_AR:			c_int = 0x70000;
+pub const ARCHIVE_FORMAT_AR_GNU:		c_int = ARCHIVE_FORMAT_AR | 1;
+pub const ARCHIVE_FORMAT_AR_BSD:		c_int = ARCHIVE_FORMAT_AR | 2;
+pub const ARCHIVE_FORMAT_MTREE:			c_int = 0x80000;
+pub const ARCHIVE_FORMAT_RAW:			c_int = 0x90000;
+pub const ARCHIVE_FORMAT_XAR:			c_int = 0xA0000;
+pub const ARCHIVE_FORMAT_LHA:			c_int = 0xB0000;
+pub const ARCHIVE_FORMAT_CAB:			c_int = 0xC0000;
+pub const ARCHIVE_FORMAT_RAR:			c_int = 0xD0000;
+pub const ARCHIVE_FORMAT_7ZIP:			c_int = 0xE0000;
+pub const ARCHIVE_FORMAT_WARC:			c_int = 0xF0000;
+pub const ARCHIVE_FORMAT_RAR_V5:		c_int = 0x100000;
+
 /* The "flags" argument selects optional behavior, 'OR' the flags you want. */
 
 /* Default: Do not try to set owner/group. */
@@ -162,6 +226,10 @@ extern "C" {
      -> c_int;
      pub fn archive_read_support_filter_xz(arg1: *mut Struct_archive)
      -> c_int;
+     pub fn archive_read_support_filter_lz4(arg1: *mut Struct_archive)
+     -> c_int;
+     pub fn archive_read_support_filter_zstd(arg1: *mut Struct_archive)
+     -> c_int;
      pub fn archive_read_support_format_7zip(arg1: *mut Struct_archive)
      -> c_int;
      pub fn archive_read_support_format_all(arg1: *mut Struct_archive)
@@ -195,6 +263,14 @@ extern "C" {
      -> c_int;
      pub fn archive_read_support_format_zip(arg1: *mut Struct_archive)
      -> c_int;
+     pub fn archive_read_support_format_zip_streamable(arg1: *mut Struct_archive)
+     -> c_int;
+     pub fn archive_read_support_format_zip_seekable(arg1: *mut Struct_archive)
+     -> c_int;
+     pub fn archive_read_support_format_rar5(arg1: *mut Struct_archive)
+     -> c_int;
+     pub fn archive_read_support_format_warc(arg1: *mut Struct_archive)
+     -> c_int;
      pub fn archive_read_set_format(arg1: *mut Struct_archive,
                                    arg2: c_int) -> c_int;
      pub fn archive_read_append_filter(arg1: *mut Struct_archive,
@@ -211,40 +287,22 @@ extern "C" {
                                                         arg4: size_t)
      -> c_int;
      pub fn archive_read_set_open_callback(arg1: *mut Struct_archive,
-                                          arg2:
-                                              *mut ::std::option::Option<extern "C" fn()
-                                                                             ->
-                                                                                 c_int>)
+                                          arg2: ::std::option::Option<archive_open_callback>)
      -> c_int;
      pub fn archive_read_set_read_callback(arg1: *mut Struct_archive,
-                                          arg2:
-                                              *mut ::std::option::Option<extern "C" fn()
-                                                                             ->
-                                                                                 ssize_t>)
+                                          arg2: ::std::option::Option<archive_read_callback>)
      -> c_int;
      pub fn archive_read_set_seek_callback(arg1: *mut Struct_archive,
-                                          arg2:
-                                              *mut ::std::option::Option<extern "C" fn()
-                                                                             ->
-                                                                                 i64>)
+                                          arg2: ::std::option::Option<archive_seek_callback>)
      -> c_int;
      pub fn archive_read_set_skip_callback(arg1: *mut Struct_archive,
-                                          arg2:
-                                              *mut ::std::option::Option<extern "C" fn()
-                                                                             ->
-                                                                                 i64>)
+                                          arg2: ::std::option::Option<archive_skip_callback>)
      -> c_int;
      pub fn archive_read_set_close_callback(arg1: *mut Struct_archive,
-                                           arg2:
-                                               *mut ::std::option::Option<extern "C" fn()
-                                                                              ->
-                                                                                  c_int>)
+                                           arg2: ::std::option::Option<archive_close_callback>)
      -> c_int;
      pub fn archive_read_set_switch_callback(arg1: *mut Struct_archive,
-                                            arg2:
-                                                *mut ::std::option::Option<extern "C" fn()
-                                                                               ->
-                                                                                   c_int>)
+                                            arg2: ::std::option::Option<archive_switch_callback>)
      -> c_int;
      pub fn archive_read_set_callback_data(arg1: *mut Struct_archive,
                                           arg2: *mut c_void)
@@ -362,6 +420,14 @@ extern "C" {
      pub fn archive_read_set_options(_a: *mut Struct_archive,
                                     opts: *const c_char)
      -> c_int;
+     pub fn archive_read_add_passphrase(arg1: *mut Struct_archive,
+                                       arg2: *const c_char) -> c_int;
+     pub fn archive_read_set_passphrase_callback(arg1: *mut Struct_archive,
+                                                client_data: *mut c_void,
+                                                arg2: ::std::option::Option<archive_passphrase_callback>)
+     -> c_int;
+     pub fn archive_read_has_encrypted_entries(arg1: *mut Struct_archive)
+     -> c_int;
      pub fn archive_read_extract(arg1: *mut Struct_archive,
                                 arg2: *mut Struct_archive_entry,
                                 flags: c_int) -> c_int;
@@ -496,17 +562,9 @@ extern "C" {
      -> c_int;
      pub fn archive_write_open(arg1: *mut Struct_archive,
                               arg2: *mut c_void,
-                              arg3:
-                                  *mut ::std::option::Option<extern "C" fn()
-                                                                 ->
-                                                                     c_int>,
-                              arg4:
-                                  *mut ::std::option::Option<extern "C" fn()
-                                                                 -> ssize_t>,
-                              arg5:
-                                  *mut ::std::option::Option<extern "C" fn()
-                                                                 ->
-                                                                     c_int>)
+                              arg3: ::std::option::Option<archive_open_callback>,
+                              arg4: ::std::option::Option<archive_write_callback>,
+                              arg5: ::std::option::Option<archive_close_callback>)
      -> c_int;
      pub fn archive_write_open_fd(arg1: *mut Struct_archive,
                                  _fd: c_int) -> c_int;
@@ -558,6 +616,12 @@ extern "C" {
      pub fn archive_write_set_options(_a: *mut Struct_archive,
                                      opts: *const c_char)
      -> c_int;
+     pub fn archive_write_set_passphrase(_a: *mut Struct_archive,
+                                        p: *const c_char) -> c_int;
+     pub fn archive_write_set_passphrase_callback(arg1: *mut Struct_archive,
+                                                 client_data: *mut c_void,
+                                                 arg2: ::std::option::Option<archive_passphrase_callback>)
+     -> c_int;
      pub fn archive_write_disk_new() -> *mut Struct_archive;
      pub fn archive_write_disk_set_skip_file(arg1: *mut Struct_archive,
                                             arg2: i64, arg3: i64)
@@ -1034,6 +1098,12 @@ extern "C" {
      pub fn archive_entry_copy_mac_metadata(arg1: *mut Struct_archive_entry,
                                            arg2: *const c_void,
                                            arg3: size_t) -> ();
+     pub fn archive_entry_is_data_encrypted(arg1: *mut Struct_archive_entry)
+     -> c_int;
+     pub fn archive_entry_is_metadata_encrypted(arg1: *mut Struct_archive_entry)
+     -> c_int;
+     pub fn archive_entry_is_encrypted(arg1: *mut Struct_archive_entry)
+     -> c_int;
      pub fn archive_entry_acl_clear(arg1: *mut Struct_archive_entry) -> ();
      pub fn archive_entry_acl_add_entry(arg1: *mut Struct_archive_entry,
                                        arg2: c_int,
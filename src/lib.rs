@@ -11,31 +11,139 @@ mod ffi;
 use ffi::archive::*;
 
 use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::error::Error;
 use std::ffi::CStr;
 use std::ffi::CString;
-use std::io::{Read, Seek};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::marker::PhantomData;
+#[cfg(unix)]
+use std::os::unix::io::AsRawFd;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
 use std::ptr;
-use std::rc::Rc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-extern crate time;
-use time::Duration;
-
-#[derive(PartialEq, Clone)]
-pub struct Reader {
-    handler: Rc<*mut Struct_archive>,
+/// Reads an archive. The lifetime is the one of the memory or file
+/// descriptor the archive is read from, see `open_memory` and `open_fd`.
+/// The reader owns the archive and frees it when dropped.
+pub struct Reader<'a> {
+    handler: *mut Struct_archive,
+    callback_data: CallbackData,
+    warnings: Warnings,
+    failed_entries: FailedEntries,
+    done: bool,
+    source: PhantomData<&'a [u8]>,
 }
 
+// libarchive handles may move between threads as long as they are not used
+// concurrently, and everything the reader owns for callbacks is `Send`. It is
+// not `Sync`, so shared borrows like entries stay on one thread.
+unsafe impl<'a> Send for Reader<'a> {}
+
 #[derive(Debug)]
 pub struct AllocationError;
-#[derive(Debug)]
-pub enum ArchiveError {
-    Ok,
+/// The libarchive return code behind an `ArchiveError`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
     Warn,
     Failed,
     Retry,
     Eof,
     Fatal,
+    Unknown(c_int),
+}
+
+/// A failed libarchive call, with the errno and message libarchive recorded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArchiveError {
+    code: ErrorCode,
+    errno: i32,
+    message: Option<String>,
+    io_kind: Option<io::ErrorKind>,
+}
+
+impl ArchiveError {
+    pub fn code(&self) -> ErrorCode {
+        self.code
+    }
+
+    /// The errno libarchive recorded, 0 if the failure did not come from the system.
+    pub fn errno(&self) -> i32 {
+        self.errno
+    }
+
+    pub fn message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+
+    /// The kind of the `io::Error` of a stream the archive is read from, if
+    /// that is what failed.
+    pub fn io_error_kind(&self) -> Option<io::ErrorKind> {
+        self.io_kind
+    }
+}
+
+impl fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.message {
+            Some(ref message) => f.write_str(message),
+            None => match self.code {
+                ErrorCode::Warn => f.write_str("libarchive warning"),
+                ErrorCode::Failed => f.write_str("libarchive operation failed"),
+                ErrorCode::Retry => f.write_str("libarchive operation should be retried"),
+                ErrorCode::Eof => f.write_str("end of archive"),
+                ErrorCode::Fatal => f.write_str("fatal libarchive error"),
+                ErrorCode::Unknown(code) => write!(f, "unknown libarchive return code {}", code),
+            },
+        }
+    }
+}
+
+impl Error for ArchiveError {}
+
+impl From<ArchiveError> for io::Error {
+    fn from(err: ArchiveError) -> io::Error {
+        let kind = if let Some(kind) = err.io_kind {
+            kind
+        } else if err.errno == ARCHIVE_ERRNO_FILE_FORMAT {
+            io::ErrorKind::InvalidData
+        } else if err.errno > 0 {
+            io::Error::from_raw_os_error(err.errno).kind()
+        } else {
+            match err.code {
+                ErrorCode::Retry => io::ErrorKind::Interrupted,
+                ErrorCode::Eof => io::ErrorKind::UnexpectedEof,
+                _ => io::ErrorKind::Other,
+            }
+        };
+        io::Error::new(kind, err)
+    }
+}
+
+/// Totals of an archive, returned by `close`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stats {
+    /// Size of the archive itself, i.e. after compression. Always 0 for
+    /// `WriterToDisk`.
+    pub compressed_bytes: i64,
+    /// Bytes before compression. Always 0 for `WriterToDisk`.
+    pub uncompressed_bytes: i64,
+    pub file_count: i32,
+    /// Warnings which were not taken yet, including those of closing.
+    pub warnings: Vec<ArchiveError>,
+}
+
+/// An entry whose header could not be read, see `Reader::skip_failed_entries`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FailedEntry {
+    /// The pathname, if libarchive got that far.
+    pub pathname: Option<String>,
+    pub error: ArchiveError,
 }
+
 #[derive(Debug)]
 pub enum ArchiveExtractFlag {
     Owner,
@@ -87,13 +195,147 @@ pub enum ArchiveFilter {
     // TODO : Program(&str)
     Xz,
 }
-pub enum ArchiveEntryIOType {
-    ReaderEntry,
-    WriterEntry,
+/// Formats which a `Reader` can be restricted to.
+pub enum ReadFormat {
+    _7Zip,
+    Ar,
+    Cab,
+    Cpio,
+    Empty,
+    Gnutar,
+    Iso9660,
+    Lha,
+    Mtree,
+    Rar,
+    Rar5,
+    Raw,
+    Tar,
+    Warc,
+    Xar,
+    Zip,
+    Zip_Streamable,
+    Zip_Seekable,
+}
+
+/// Filters which a `Reader` can be restricted to.
+pub enum ReadFilter<'a> {
+    Bzip2,
+    Compress,
+    Grzip,
+    Gzip,
+    Lrzip,
+    Lz4,
+    Lzip,
+    Lzma,
+    Lzop,
+    None,
+    /// Decompresses through an external program, e.g. `unzstd`.
+    Program(&'a str),
+    Rpm,
+    Uu,
+    Xz,
+    Zstd,
+}
+
+/// The format libarchive detected, see `Reader::format`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DetectedFormat {
+    Cpio,
+    Cpio_Posix,
+    Cpio_Bin_Le,
+    Cpio_Bin_Be,
+    Cpio_Svr4_Nocrc,
+    Cpio_Svr4_Crc,
+    Cpio_Afio_Large,
+    Cpio_Pwb,
+    Shar,
+    Shar_Base,
+    Shar_Dump,
+    Tar,
+    Tar_Ustar,
+    Tar_Pax_Interchange,
+    Tar_Pax_Restricted,
+    Tar_Gnutar,
+    Iso9660,
+    Iso9660_Rockridge,
+    Zip,
+    Empty,
+    Ar,
+    Ar_Gnu,
+    Ar_Bsd,
+    Mtree,
+    Raw,
+    Xar,
+    Lha,
+    Cab,
+    Rar,
+    _7Zip,
+    Warc,
+    Rar5,
+    Unknown(c_int),
+}
+
+/// A filter of the chain libarchive detected, see `Reader::filters`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DetectedFilter {
+    None,
+    Gzip,
+    Bzip2,
+    Compress,
+    Program,
+    Lzma,
+    Xz,
+    Uu,
+    Rpm,
+    Lzip,
+    Lrzip,
+    Lzop,
+    Grzip,
+    Lz4,
+    Zstd,
+    Unknown(c_int),
+}
+
+#[derive(Debug, Clone)]
+pub struct FilterInfo {
+    pub filter: DetectedFilter,
+    pub name: String,
+    /// Bytes which went through the filter so far.
+    pub bytes: i64,
+}
+
+/// Options of the read format and filter modules, see `Reader::set_option`.
+pub enum ReadOption<'a> {
+    /// Charset of the pathnames in the headers of any format, e.g. `CP932`.
+    HdrCharset(&'a str),
+    /// Read archives written by libarchive 2.x, which stored pathnames in
+    /// the current locale instead of UTF-8.
+    Compat2x,
+    /// Do not verify the CRC32 of zip entries.
+    ZipIgnoreCrc32,
+    /// Use the Mac OS metadata stored in zip extra fields.
+    ZipMacExt,
+    /// Keep reading past the end-of-archive marker of concatenated tarballs.
+    TarReadConcatenatedArchives,
+    /// Use the Mac OS metadata stored in tar headers.
+    TarMacExt,
+    /// Whether Joliet extensions of ISO images are used.
+    Iso9660Joliet(bool),
+    /// Whether Rock Ridge extensions of ISO images are used.
+    Iso9660RockRidge(bool),
+    /// Let mtree archives fill in entries from the file system.
+    MtreeCheckFs,
+}
+
+pub enum ZipEncryption {
+    Traditional,
+    Aes128,
+    Aes256,
 }
 
+/// The type of an entry. libarchive's `AE_IFMT` is the mask of these and
+/// not a type itself, so it is left out.
 pub enum ArchiveEntryFiletype {
-    AE_IFMT,
     AE_IFREG,
     AE_IFLNK,
     AE_IFSOCK,
@@ -102,6 +344,13 @@ pub enum ArchiveEntryFiletype {
     AE_IFDIR,
     AE_IFIFO,
 }
+
+/// The kind of a device node, see `Writer::add_device`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceKind {
+    Character,
+    Block,
+}
 /*
 impl fmt::Debug for AllocationError {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
@@ -115,17 +364,126 @@ impl fmt::Debug for AllocationError {
     }
 }*/
 
-fn code_to_error(code: c_int) -> ArchiveError {
+// a misuse caught before calling libarchive, reported as libarchive would
+fn usage_error(message: &str) -> ArchiveError {
+    ArchiveError {
+        code: ErrorCode::Fatal,
+        errno: ARCHIVE_ERRNO_PROGRAMMER,
+        message: Some(message.to_string()),
+        io_kind: None,
+    }
+}
+
+fn code_to_error(code: c_int) -> ErrorCode {
     match code {
-        ARCHIVE_OK => ArchiveError::Ok,
-        ARCHIVE_WARN => ArchiveError::Warn,
-        ARCHIVE_FAILED => ArchiveError::Failed,
-        ARCHIVE_RETRY => ArchiveError::Retry,
-        ARCHIVE_EOF => ArchiveError::Eof,
-        ARCHIVE_FATAL => ArchiveError::Fatal,
-        _ => {
-            panic!();
+        ARCHIVE_WARN => ErrorCode::Warn,
+        ARCHIVE_FAILED => ErrorCode::Failed,
+        ARCHIVE_RETRY => ErrorCode::Retry,
+        ARCHIVE_EOF => ErrorCode::Eof,
+        ARCHIVE_FATAL => ErrorCode::Fatal,
+        _ => ErrorCode::Unknown(code),
+    }
+}
+
+/// Warnings of calls which succeeded nevertheless, e.g. an unknown pax
+/// keyword. Recorded through shared borrows, e.g. by `DataReader`.
+type Warnings = RefCell<Vec<ArchiveError>>;
+
+/// Entries skipped by `Reader::skip_failed_entries`, `None` unless that
+/// mode is on.
+type FailedEntries = Option<Vec<FailedEntry>>;
+
+// a warning means that the call succeeded, so it is recorded instead of failing
+unsafe fn check_result(
+    handler: *mut Struct_archive,
+    warnings: &Warnings,
+    res: c_int,
+) -> Result<(), ArchiveError> {
+    match res {
+        ARCHIVE_OK => Ok(()),
+        ARCHIVE_WARN => {
+            warnings.borrow_mut().push(archive_error(handler, res));
+            Ok(())
         }
+        _ => Err(archive_error(handler, res)),
+    }
+}
+
+// collects errno and message of the last failure, which libarchive does not
+// record for the end of the archive
+unsafe fn archive_error(handler: *mut Struct_archive, code: c_int) -> ArchiveError {
+    if code == ARCHIVE_EOF {
+        return ArchiveError {
+            code: ErrorCode::Eof,
+            errno: 0,
+            message: None,
+            io_kind: None,
+        };
+    }
+    ArchiveError {
+        code: code_to_error(code),
+        errno: archive_errno(handler),
+        message: wrap_to_option_string(archive_error_string(handler)),
+        io_kind: None,
+    }
+}
+
+fn code_to_format(code: c_int) -> DetectedFormat {
+    match code {
+        ARCHIVE_FORMAT_CPIO => DetectedFormat::Cpio,
+        ARCHIVE_FORMAT_CPIO_POSIX => DetectedFormat::Cpio_Posix,
+        ARCHIVE_FORMAT_CPIO_BIN_LE => DetectedFormat::Cpio_Bin_Le,
+        ARCHIVE_FORMAT_CPIO_BIN_BE => DetectedFormat::Cpio_Bin_Be,
+        ARCHIVE_FORMAT_CPIO_SVR4_NOCRC => DetectedFormat::Cpio_Svr4_Nocrc,
+        ARCHIVE_FORMAT_CPIO_SVR4_CRC => DetectedFormat::Cpio_Svr4_Crc,
+        ARCHIVE_FORMAT_CPIO_AFIO_LARGE => DetectedFormat::Cpio_Afio_Large,
+        ARCHIVE_FORMAT_CPIO_PWB => DetectedFormat::Cpio_Pwb,
+        ARCHIVE_FORMAT_SHAR => DetectedFormat::Shar,
+        ARCHIVE_FORMAT_SHAR_BASE => DetectedFormat::Shar_Base,
+        ARCHIVE_FORMAT_SHAR_DUMP => DetectedFormat::Shar_Dump,
+        ARCHIVE_FORMAT_TAR => DetectedFormat::Tar,
+        ARCHIVE_FORMAT_TAR_USTAR => DetectedFormat::Tar_Ustar,
+        ARCHIVE_FORMAT_TAR_PAX_INTERCHANGE => DetectedFormat::Tar_Pax_Interchange,
+        ARCHIVE_FORMAT_TAR_PAX_RESTRICTED => DetectedFormat::Tar_Pax_Restricted,
+        ARCHIVE_FORMAT_TAR_GNUTAR => DetectedFormat::Tar_Gnutar,
+        ARCHIVE_FORMAT_ISO9660 => DetectedFormat::Iso9660,
+        ARCHIVE_FORMAT_ISO9660_ROCKRIDGE => DetectedFormat::Iso9660_Rockridge,
+        ARCHIVE_FORMAT_ZIP => DetectedFormat::Zip,
+        ARCHIVE_FORMAT_EMPTY => DetectedFormat::Empty,
+        ARCHIVE_FORMAT_AR => DetectedFormat::Ar,
+        ARCHIVE_FORMAT_AR_GNU => DetectedFormat::Ar_Gnu,
+        ARCHIVE_FORMAT_AR_BSD => DetectedFormat::Ar_Bsd,
+        ARCHIVE_FORMAT_MTREE => DetectedFormat::Mtree,
+        ARCHIVE_FORMAT_RAW => DetectedFormat::Raw,
+        ARCHIVE_FORMAT_XAR => DetectedFormat::Xar,
+        ARCHIVE_FORMAT_LHA => DetectedFormat::Lha,
+        ARCHIVE_FORMAT_CAB => DetectedFormat::Cab,
+        ARCHIVE_FORMAT_RAR => DetectedFormat::Rar,
+        ARCHIVE_FORMAT_7ZIP => DetectedFormat::_7Zip,
+        ARCHIVE_FORMAT_WARC => DetectedFormat::Warc,
+        ARCHIVE_FORMAT_RAR_V5 => DetectedFormat::Rar5,
+        _ => DetectedFormat::Unknown(code),
+    }
+}
+
+fn code_to_filter(code: c_int) -> DetectedFilter {
+    match code {
+        ARCHIVE_FILTER_NONE => DetectedFilter::None,
+        ARCHIVE_FILTER_GZIP => DetectedFilter::Gzip,
+        ARCHIVE_FILTER_BZIP2 => DetectedFilter::Bzip2,
+        ARCHIVE_FILTER_COMPRESS => DetectedFilter::Compress,
+        ARCHIVE_FILTER_PROGRAM => DetectedFilter::Program,
+        ARCHIVE_FILTER_LZMA => DetectedFilter::Lzma,
+        ARCHIVE_FILTER_XZ => DetectedFilter::Xz,
+        ARCHIVE_FILTER_UU => DetectedFilter::Uu,
+        ARCHIVE_FILTER_RPM => DetectedFilter::Rpm,
+        ARCHIVE_FILTER_LZIP => DetectedFilter::Lzip,
+        ARCHIVE_FILTER_LRZIP => DetectedFilter::Lrzip,
+        ARCHIVE_FILTER_LZOP => DetectedFilter::Lzop,
+        ARCHIVE_FILTER_GRZIP => DetectedFilter::Grzip,
+        ARCHIVE_FILTER_LZ4 => DetectedFilter::Lz4,
+        ARCHIVE_FILTER_ZSTD => DetectedFilter::Zstd,
+        _ => DetectedFilter::Unknown(code),
     }
 }
 
@@ -156,71 +514,389 @@ fn flags_to_code(flags: Vec<ArchiveExtractFlag>) -> c_int {
     flags_code
 }
 
+/// Everything libarchive holds a pointer to through callback client data.
+/// It is owned by the handle of the archive, which frees the archive first.
+type CallbackData = Vec<Box<dyn Any + Send>>;
+
+trait ReadSeek: Read + Seek {}
+impl<T: Read + Seek> ReadSeek for T {}
+
+enum StreamSource {
+    Plain(Box<dyn Read + Send>),
+    Seekable(Box<dyn ReadSeek + Send>),
+}
+
+/// What went wrong in a callback, kept until the `Reader` method which
+/// called into libarchive returns.
+enum CallbackFailure {
+    Panic(Box<dyn Any + Send>),
+    Io(io::Error),
+    // left behind once the failure was returned, as the source or sink must
+    // not be used again
+    Reported,
+}
+
 struct ReadContainer {
-    reader: Box<dyn Read>,
+    source: StreamSource,
     buffer: Vec<u8>,
-    seeker: Option<Box<dyn Seek>>,
+    failure: Cell<Option<CallbackFailure>>,
 }
 
 impl ReadContainer {
-    fn read_bytes(&mut self) -> std::io::Result<usize> {
-        self.reader.read(&mut self.buffer[..])
+    fn new(source: StreamSource) -> ReadContainer {
+        ReadContainer {
+            source,
+            buffer: vec![0; 8192],
+            failure: Cell::new(None),
+        }
+    }
+
+    fn read_bytes(&mut self) -> io::Result<usize> {
+        let buffer = &mut self.buffer[..];
+        match self.source {
+            StreamSource::Plain(ref mut reader) => retry(|| reader.read(buffer)),
+            StreamSource::Seekable(ref mut reader) => retry(|| reader.read(buffer)),
+        }
+    }
+
+    fn seeker(&mut self) -> Option<&mut dyn ReadSeek> {
+        match self.source {
+            StreamSource::Plain(_) => None,
+            StreamSource::Seekable(ref mut seeker) => Some(&mut **seeker),
+        }
+    }
+}
+
+/// Client data of callbacks which record their failure instead of letting it
+/// unwind into libarchive.
+trait CallbackContainer: Sized {
+    fn failure(&self) -> &Cell<Option<CallbackFailure>>;
+
+    // once a stream failed, libarchive must not carry on with it
+    fn has_failed(&self) -> bool {
+        let failure = self.failure().take();
+        let failed = failure.is_some();
+        self.failure().set(failure);
+        failed
+    }
+
+    fn take_failure(&self) -> Option<CallbackFailure> {
+        match self.failure().take() {
+            None => None,
+            Some(CallbackFailure::Reported) => {
+                self.failure().set(Some(CallbackFailure::Reported));
+                None
+            }
+            Some(failure) => {
+                self.failure().set(Some(CallbackFailure::Reported));
+                Some(failure)
+            }
+        }
+    }
+
+    // runs a callback on the container, recording a panic or an io::Error
+    unsafe fn call<T, F>(arch: *mut Struct_archive, client_data: *mut c_void, f: F) -> Option<T>
+    where
+        F: FnOnce(&mut Self) -> io::Result<T>,
+    {
+        let container = &mut *(client_data as *mut Self);
+        if container.has_failed() {
+            set_error(arch, &io::Error::other("the stream failed before"));
+            return None;
+        }
+        match panic::catch_unwind(AssertUnwindSafe(|| f(&mut *container))) {
+            Ok(Ok(value)) => Some(value),
+            Ok(Err(err)) => {
+                set_error(arch, &err);
+                container.failure().set(Some(CallbackFailure::Io(err)));
+                None
+            }
+            Err(payload) => {
+                container
+                    .failure()
+                    .set(Some(CallbackFailure::Panic(payload)));
+                None
+            }
+        }
+    }
+}
+
+impl CallbackContainer for ReadContainer {
+    fn failure(&self) -> &Cell<Option<CallbackFailure>> {
+        &self.failure
+    }
+}
+
+trait WriteSink: Write + Send {
+    fn as_any(&self) -> &dyn Any;
+    fn into_any(self: Box<Self>) -> Box<dyn Any>;
+}
+
+impl<T: Write + Send + 'static> WriteSink for T {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+/// The buffer of `Writer::open_memory`, which the `MemoryWriter` borrows.
+struct FixedBuffer {
+    memory: *mut u8,
+    len: usize,
+    used: usize,
+}
+
+// it stands for the `&mut [u8]` borrowed by the `MemoryWriter`
+unsafe impl Send for FixedBuffer {}
+
+impl Write for FixedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.len() > self.len - self.used {
+            return Err(io::Error::new(io::ErrorKind::WriteZero, "buffer too small"));
+        }
+        unsafe {
+            ptr::copy_nonoverlapping(buf.as_ptr(), self.memory.add(self.used), buf.len());
+        }
+        self.used += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+struct WriteContainer {
+    sink: Box<dyn WriteSink>,
+    failure: Cell<Option<CallbackFailure>>,
+}
+
+impl CallbackContainer for WriteContainer {
+    fn failure(&self) -> &Cell<Option<CallbackFailure>> {
+        &self.failure
+    }
+}
+
+impl CallbackContainer for PassphraseCallback {
+    fn failure(&self) -> &Cell<Option<CallbackFailure>> {
+        &self.failure
+    }
+}
+
+extern "C" fn arch_write(
+    arch: *mut Struct_archive,
+    _client_data: *mut c_void,
+    _buffer: *const c_void,
+    _length: size_t,
+) -> ssize_t {
+    unsafe {
+        let written = WriteContainer::call(arch, _client_data, |wc| {
+            if _length > 0 {
+                let data = std::slice::from_raw_parts(_buffer as *const u8, _length);
+                wc.sink.write_all(data)?;
+            }
+            Ok(_length)
+        });
+        match written {
+            Some(length) => length as ssize_t,
+            None => -1,
+        }
+    }
+}
+
+extern "C" fn arch_write_close(arch: *mut Struct_archive, _client_data: *mut c_void) -> c_int {
+    unsafe {
+        match WriteContainer::call(arch, _client_data, |wc| wc.sink.flush()) {
+            Some(()) => ARCHIVE_OK,
+            None => ARCHIVE_FATAL,
+        }
+    }
+}
+
+// takes what went wrong in any callback of the archive
+fn take_callback_failure(callback_data: &CallbackData) -> Option<CallbackFailure> {
+    callback_data.iter().find_map(|data| {
+        if let Some(rc) = data.downcast_ref::<ReadContainer>() {
+            rc.take_failure()
+        } else if let Some(wc) = data.downcast_ref::<WriteContainer>() {
+            wc.take_failure()
+        } else if let Some(pc) = data.downcast_ref::<PassphraseCallback>() {
+            pc.take_failure()
+        } else {
+            None
+        }
+    })
+}
+
+fn io_archive_error(code: c_int, err: &io::Error) -> ArchiveError {
+    ArchiveError {
+        code: code_to_error(code),
+        errno: err.raw_os_error().unwrap_or(0),
+        message: Some(err.to_string()),
+        io_kind: Some(err.kind()),
+    }
+}
+
+// a failed callback is what actually went wrong, whatever libarchive made
+// of it: a panic is resumed and an io::Error keeps its kind
+unsafe fn check_callbacks(
+    handler: *mut Struct_archive,
+    warnings: &Warnings,
+    callback_data: &CallbackData,
+    res: c_int,
+) -> Result<(), ArchiveError> {
+    match take_callback_failure(callback_data) {
+        None | Some(CallbackFailure::Reported) => check_result(handler, warnings, res),
+        Some(CallbackFailure::Panic(payload)) => panic::resume_unwind(payload),
+        Some(CallbackFailure::Io(err)) => {
+            let code = if res == ARCHIVE_OK || res == ARCHIVE_WARN {
+                ARCHIVE_FATAL
+            } else {
+                res
+            };
+            Err(io_archive_error(code, &err))
+        }
     }
 }
 
+unsafe fn set_error(arch: *mut Struct_archive, err: &io::Error) {
+    set_error_message(arch, err.raw_os_error().unwrap_or(0), &err.to_string());
+}
+
+// libarchive formats the message like printf, so it is passed as an argument
+unsafe fn set_error_message(arch: *mut Struct_archive, errno: c_int, message: &str) {
+    let descr = CString::new(message.replace('\0', "")).unwrap_or_default();
+    archive_set_error(
+        arch,
+        errno,
+        b"%s\0".as_ptr() as *const c_char,
+        descr.as_ptr(),
+    );
+}
+
 extern "C" fn arch_read(
     arch: *mut Struct_archive,
     _client_data: *mut c_void,
     _buffer: *mut *mut c_void,
 ) -> ssize_t {
     unsafe {
-        // use client_data as pointer to ReadContainer struct
-        let mut rc = Box::from_raw(_client_data as *mut ReadContainer);
-        *_buffer = rc.buffer.as_mut_ptr() as *mut c_void;
-        let size = rc.read_bytes();
-        let _ = Box::into_raw(rc);
-
-        if let Err(err) = size {
-            let descr = CString::new(err.to_string()).unwrap();
-            archive_set_error(arch, err.raw_os_error().unwrap_or(0), descr.as_ptr());
-            -1
-        } else {
-            size.unwrap() as ssize_t
+        let size = ReadContainer::call(arch, _client_data, |rc| {
+            let size = rc.read_bytes()?;
+            *_buffer = rc.buffer.as_mut_ptr() as *mut c_void;
+            Ok(size)
+        });
+        match size {
+            Some(size) => size as ssize_t,
+            None => -1,
         }
     }
 }
 
-#[allow(unused_variables)]
-extern "C" fn arch_close(arch: *mut Struct_archive, _client_data: *mut c_void) -> c_int {
+// an interrupted call, e.g. by a signal while reading a pipe, is tried again
+fn retry<T>(mut call: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+    loop {
+        match call() {
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+            result => return result,
+        }
+    }
+}
+
+// the ReadContainer is owned by CallbackData and freed together with the archive,
+// as libarchive may switch back to a stream it has already closed
+extern "C" fn arch_close(_: *mut Struct_archive, _client_data: *mut c_void) -> c_int {
+    ARCHIVE_OK
+}
+
+// a skip can not fail, so after an error nothing is skipped and the
+// following read reports the failure recorded in the container
+extern "C" fn arch_skip(arch: *mut Struct_archive, _client_data: *mut c_void, request: i64) -> i64 {
     unsafe {
-        let rc = Box::from_raw(_client_data as *mut ReadContainer);
-        ARCHIVE_OK
+        ReadContainer::call(arch, _client_data, |rc| match rc.seeker() {
+            None => Ok(0),
+            Some(seeker) => {
+                let before = retry(|| seeker.stream_position())?;
+                let after = retry(|| seeker.seek(SeekFrom::Current(request)))?;
+                Ok(after as i64 - before as i64)
+            }
+        })
+        .unwrap_or(0)
     }
 }
 
-extern "C" fn arch_skip(_: *mut Struct_archive, _client_data: *mut c_void, request: i64) -> i64 {
+extern "C" fn arch_seek(
+    arch: *mut Struct_archive,
+    _client_data: *mut c_void,
+    offset: i64,
+    whence: c_int,
+) -> i64 {
+    let pos = match whence {
+        SEEK_SET => SeekFrom::Start(offset as u64),
+        SEEK_CUR => SeekFrom::Current(offset),
+        SEEK_END => SeekFrom::End(offset),
+        _ => return ARCHIVE_FATAL as i64,
+    };
     unsafe {
-        let mut rc = Box::from_raw(_client_data as *mut ReadContainer);
+        let pos = ReadContainer::call(arch, _client_data, |rc| match rc.seeker() {
+            Some(seeker) => retry(|| seeker.seek(pos)),
+            None => Err(io::Error::from(io::ErrorKind::Unsupported)),
+        });
+        match pos {
+            Some(pos) => pos as i64,
+            None => ARCHIVE_FATAL as i64,
+        }
+    }
+}
 
-        // we can't return error code here, but if we return 0 normal read will be called, where error code will be set
-        if rc.seeker.is_none() {
-            let _ = Box::into_raw(rc);
-            return 0;
+// libarchive expects a stream it switches to to be read from its start,
+// like a file that has just been opened
+extern "C" fn arch_switch(
+    arch: *mut Struct_archive,
+    _: *mut c_void,
+    _client_data2: *mut c_void,
+) -> c_int {
+    unsafe {
+        let res = ReadContainer::call(arch, _client_data2, |rc| match rc.seeker() {
+            None => Ok(()),
+            Some(seeker) => retry(|| seeker.seek(SeekFrom::Start(0))).map(|_| ()),
+        });
+        match res {
+            Some(()) => ARCHIVE_OK,
+            None => ARCHIVE_FATAL,
         }
-        let size = rc
-            .seeker
-            .as_mut()
-            .unwrap()
-            .seek(std::io::SeekFrom::Current(request))
-            .unwrap_or(0);
+    }
+}
+
+struct PassphraseCallback {
+    callback: Box<dyn FnMut() -> Option<String> + Send>,
+    // libarchive only borrows the returned passphrase until the next call
+    current: Option<CString>,
+    failure: Cell<Option<CallbackFailure>>,
+}
 
-        let _ = Box::into_raw(rc);
-        size as i64
+extern "C" fn arch_passphrase(_: *mut Struct_archive, _client_data: *mut c_void) -> *const c_char {
+    unsafe {
+        let pc = &mut *(_client_data as *mut PassphraseCallback);
+        let callback = &mut pc.callback;
+        pc.current = match panic::catch_unwind(AssertUnwindSafe(callback)) {
+            Ok(passphrase) => passphrase.and_then(|p| CString::new(p).ok()),
+            Err(payload) => {
+                pc.failure.set(Some(CallbackFailure::Panic(payload)));
+                None
+            }
+        };
+        match pc.current {
+            Some(ref p) => p.as_ptr(),
+            None => ptr::null(),
+        }
     }
 }
 
-impl Reader {
-    pub fn new() -> Result<Reader, AllocationError> {
+impl<'a> Reader<'a> {
+    pub fn new() -> Result<Reader<'a>, AllocationError> {
         unsafe {
             let h = archive_read_new();
 
@@ -228,7 +904,12 @@ impl Reader {
                 Err(AllocationError)
             } else {
                 Ok(Reader {
-                    handler: Rc::new(h),
+                    handler: h,
+                    callback_data: CallbackData::default(),
+                    warnings: Warnings::default(),
+                    failed_entries: FailedEntries::default(),
+                    done: false,
+                    source: PhantomData,
                 })
             }
         }
@@ -236,151 +917,624 @@ impl Reader {
 
     pub fn support_filter_all(self) -> Self {
         unsafe {
-            archive_read_support_filter_all(*self.handler);
+            archive_read_support_filter_all(self.handler);
         }
         self
     }
 
     pub fn support_format_all(self) -> Self {
         unsafe {
-            archive_read_support_format_all(*self.handler);
+            archive_read_support_format_all(self.handler);
         }
         self
     }
     pub fn support_format_raw(self) -> Self {
         unsafe {
-            archive_read_support_format_raw(*self.handler);
+            archive_read_support_format_raw(self.handler);
         }
         self
     }
 
-    pub fn open_filename(self, fileName: &str, bufferSize: usize) -> Result<Self, ArchiveError> {
-        let fname = CString::new(fileName).unwrap();
+    pub fn support_format(self, format: ReadFormat) -> Self {
         unsafe {
-            let res = archive_read_open_filename(*self.handler, fname.as_ptr(), bufferSize);
-            if res == ARCHIVE_OK {
-                Ok(self)
-            } else {
-                Err(code_to_error(res))
-            }
+            match format {
+                ReadFormat::_7Zip => archive_read_support_format_7zip(self.handler),
+                ReadFormat::Ar => archive_read_support_format_ar(self.handler),
+                ReadFormat::Cab => archive_read_support_format_cab(self.handler),
+                ReadFormat::Cpio => archive_read_support_format_cpio(self.handler),
+                ReadFormat::Empty => archive_read_support_format_empty(self.handler),
+                ReadFormat::Gnutar => archive_read_support_format_gnutar(self.handler),
+                ReadFormat::Iso9660 => archive_read_support_format_iso9660(self.handler),
+                ReadFormat::Lha => archive_read_support_format_lha(self.handler),
+                ReadFormat::Mtree => archive_read_support_format_mtree(self.handler),
+                ReadFormat::Rar => archive_read_support_format_rar(self.handler),
+                ReadFormat::Rar5 => archive_read_support_format_rar5(self.handler),
+                ReadFormat::Raw => archive_read_support_format_raw(self.handler),
+                ReadFormat::Tar => archive_read_support_format_tar(self.handler),
+                ReadFormat::Warc => archive_read_support_format_warc(self.handler),
+                ReadFormat::Xar => archive_read_support_format_xar(self.handler),
+                ReadFormat::Zip => archive_read_support_format_zip(self.handler),
+                ReadFormat::Zip_Streamable => {
+                    archive_read_support_format_zip_streamable(self.handler)
+                }
+                ReadFormat::Zip_Seekable => archive_read_support_format_zip_seekable(self.handler),
+            };
         }
+        self
     }
 
-    pub fn open_memory(self, memory: &mut [u8]) -> Result<Self, ArchiveError> {
+    pub fn support_filter(self, filter: ReadFilter) -> Self {
         unsafe {
-            let memptr: *mut u8 = &mut memory[0];
-            let res = archive_read_open_memory(*self.handler, memptr as *mut c_void, memory.len());
-            if res == ARCHIVE_OK {
-                Ok(self)
-            } else {
-                Err(code_to_error(res))
-            }
+            match filter {
+                ReadFilter::Bzip2 => archive_read_support_filter_bzip2(self.handler),
+                ReadFilter::Compress => archive_read_support_filter_compress(self.handler),
+                ReadFilter::Grzip => archive_read_support_filter_grzip(self.handler),
+                ReadFilter::Gzip => archive_read_support_filter_gzip(self.handler),
+                ReadFilter::Lrzip => archive_read_support_filter_lrzip(self.handler),
+                ReadFilter::Lz4 => archive_read_support_filter_lz4(self.handler),
+                ReadFilter::Lzip => archive_read_support_filter_lzip(self.handler),
+                ReadFilter::Lzma => archive_read_support_filter_lzma(self.handler),
+                ReadFilter::Lzop => archive_read_support_filter_lzop(self.handler),
+                ReadFilter::None => archive_read_support_filter_none(self.handler),
+                ReadFilter::Program(command) => {
+                    let c_command = CString::new(command).unwrap();
+                    archive_read_support_filter_program(self.handler, c_command.as_ptr())
+                }
+                ReadFilter::Rpm => archive_read_support_filter_rpm(self.handler),
+                ReadFilter::Uu => archive_read_support_filter_uu(self.handler),
+                ReadFilter::Xz => archive_read_support_filter_xz(self.handler),
+                ReadFilter::Zstd => archive_read_support_filter_zstd(self.handler),
+            };
         }
+        self
     }
 
-    pub fn open_stream<T: Any + Read>(self, source: T) -> Result<Self, ArchiveError> {
-        unsafe {
-            let mut rc_unboxed = ReadContainer {
-                reader: Box::new(source),
-                buffer: Vec::with_capacity(8192),
-                seeker: None,
-            };
-            for _ in 0..8192 {
-                rc_unboxed.buffer.push(0);
+    /// Reads the archive as the given format only, without format detection.
+    /// The format is registered here, so it must not also be passed to
+    /// `support_format`.
+    pub fn set_format(self, format: ReadFormat) -> Result<Self, ArchiveError> {
+        let code = match format {
+            ReadFormat::_7Zip => ARCHIVE_FORMAT_7ZIP,
+            ReadFormat::Ar => ARCHIVE_FORMAT_AR,
+            ReadFormat::Cab => ARCHIVE_FORMAT_CAB,
+            ReadFormat::Cpio => ARCHIVE_FORMAT_CPIO,
+            ReadFormat::Empty => ARCHIVE_FORMAT_EMPTY,
+            ReadFormat::Gnutar => ARCHIVE_FORMAT_TAR_GNUTAR,
+            ReadFormat::Iso9660 => ARCHIVE_FORMAT_ISO9660,
+            ReadFormat::Lha => ARCHIVE_FORMAT_LHA,
+            ReadFormat::Mtree => ARCHIVE_FORMAT_MTREE,
+            ReadFormat::Rar => ARCHIVE_FORMAT_RAR,
+            ReadFormat::Rar5 => ARCHIVE_FORMAT_RAR_V5,
+            ReadFormat::Raw => ARCHIVE_FORMAT_RAW,
+            ReadFormat::Tar => ARCHIVE_FORMAT_TAR,
+            ReadFormat::Warc => ARCHIVE_FORMAT_WARC,
+            ReadFormat::Xar => ARCHIVE_FORMAT_XAR,
+            ReadFormat::Zip | ReadFormat::Zip_Streamable | ReadFormat::Zip_Seekable => {
+                ARCHIVE_FORMAT_ZIP
             }
-            let rc = Box::new(rc_unboxed);
-
-            let res = archive_read_open(
-                *self.handler,
-                Box::into_raw(rc) as *mut c_void,
-                ptr::null_mut(),
-                arch_read,
-                arch_close,
-            );
-            if res == ARCHIVE_OK {
+        };
+        unsafe {
+            let res = archive_read_set_format(self.handler, code);
+            // a warning only tells that other formats were registered before
+            if res == ARCHIVE_OK || res == ARCHIVE_WARN {
                 Ok(self)
             } else {
-                Err(code_to_error(res))
+                Err(archive_error(self.handler, res))
             }
         }
     }
 
-    pub fn next_header(&self) -> Result<ArchiveEntryReader, ArchiveError> {
-        use ArchiveEntryIOType::*;
+    /// Adds a passphrase to try on encrypted entries. May be called several
+    /// times; libarchive tries each passphrase in turn.
+    pub fn add_passphrase(self, passphrase: &str) -> Result<Self, ArchiveError> {
+        let c_passphrase = CString::new(passphrase).unwrap();
         unsafe {
-            let mut entry: *mut Struct_archive_entry = ptr::null_mut();
-            let res = archive_read_next_header(*self.handler, &mut entry);
-            if res == ARCHIVE_OK {
-                Ok(ArchiveEntryReader {
-                    entry,
-                    handler: self.handler.clone(),
-                    iotype: ReaderEntry,
-                })
-            } else {
-                Err(code_to_error(res))
-            }
+            let res = archive_read_add_passphrase(self.handler, c_passphrase.as_ptr());
+            self.check(res).map(|()| self)
         }
     }
 
-    pub fn read_data(&self, size: size_t) -> Result<Vec<u8>, ArchiveError> {
+    /// Sets a provider asked for passphrases once the ones added with
+    /// `add_passphrase` are exhausted. Returning `None` gives up on the entry.
+    pub fn set_passphrase_callback<F>(mut self, callback: F) -> Self
+    where
+        F: FnMut() -> Option<String> + Send + 'static,
+    {
+        let mut pc = Box::new(PassphraseCallback {
+            callback: Box::new(callback),
+            current: None,
+            failure: Cell::new(None),
+        });
         unsafe {
-            let mut chunk_vec = Vec::with_capacity(size);
-            let chunk_ptr = chunk_vec.as_mut_ptr();
-            let res = archive_read_data(*self.handler, chunk_ptr as *mut c_void, size) as i32;
-            if (res == ARCHIVE_FATAL) || (res == ARCHIVE_WARN) || (res == ARCHIVE_RETRY) {
-                Err(code_to_error(res))
-            } else if res == 0 {
-                Err(code_to_error(ARCHIVE_EOF))
-            } else {
-                chunk_vec.set_len(res as usize);
-                Ok(chunk_vec)
-            }
+            archive_read_set_passphrase_callback(
+                self.handler,
+                &mut *pc as *mut PassphraseCallback as *mut c_void,
+                Some(arch_passphrase),
+            );
         }
+        self.callback_data.push(pc);
+        self
     }
-}
 
-impl Drop for ArchiveEntryReader {
-    fn drop(&mut self) {
-        use ArchiveEntryIOType::*;
-        if Rc::strong_count(&self.handler) <= 1 {
-            match self.iotype {
-                ReaderEntry => unsafe {
-                    archive_read_close(*self.handler);
-                    archive_read_free(*self.handler);
-                },
-                WriterEntry => unsafe {
-                    archive_write_close(*self.handler);
-                    archive_write_free(*self.handler);
-                },
+    /// Whether the archive contains encrypted entries, or `None` when the
+    /// format does not support encryption or it can not be told yet.
+    pub fn has_encrypted_entries(&self) -> Option<bool> {
+        unsafe {
+            match archive_read_has_encrypted_entries(self.handler) {
+                ARCHIVE_READ_FORMAT_ENCRYPTION_UNSUPPORTED
+                | ARCHIVE_READ_FORMAT_ENCRYPTION_DONT_KNOW => None,
+                res => Some(res > 0),
             }
         }
     }
-}
 
-impl Drop for Reader {
-    fn drop(&mut self) {
-        if Rc::strong_count(&self.handler) <= 1 {
-            unsafe {
-                archive_read_close(*self.handler);
-                archive_read_free(*self.handler);
+    /// Sets an option on the registered formats and filters, so it has to be
+    /// called after the `support_*` methods and before opening.
+    pub fn set_option(self, option: ReadOption) -> Result<Self, ArchiveError> {
+        let (module, name, value) = match option {
+            ReadOption::HdrCharset(charset) => (None, "hdrcharset", Some(charset)),
+            ReadOption::Compat2x => (None, "compat-2x", Some("1")),
+            ReadOption::ZipIgnoreCrc32 => (Some("zip"), "ignorecrc32", Some("1")),
+            ReadOption::ZipMacExt => (Some("zip"), "mac-ext", Some("1")),
+            ReadOption::TarReadConcatenatedArchives => {
+                (Some("tar"), "read_concatenated_archives", Some("1"))
             }
-        }
+            ReadOption::TarMacExt => (Some("tar"), "mac-ext", Some("1")),
+            ReadOption::Iso9660Joliet(on) => (Some("iso9660"), "joliet", flag_value(on)),
+            ReadOption::Iso9660RockRidge(on) => (Some("iso9660"), "rockridge", flag_value(on)),
+            ReadOption::MtreeCheckFs => (Some("mtree"), "checkfs", Some("1")),
+        };
+        let c_module = module.map(|m| CString::new(m).unwrap());
+        let c_name = CString::new(name).unwrap();
+        let c_value = value.map(|v| CString::new(v).unwrap());
+        unsafe {
+            let res = archive_read_set_option(
+                self.handler,
+                c_module.as_ref().map_or(ptr::null(), |m| m.as_ptr()),
+                c_name.as_ptr(),
+                c_value.as_ref().map_or(ptr::null(), |v| v.as_ptr()),
+            );
+            self.option_result(res)
+        }
+    }
+
+    /// Sets options from a string in the syntax of `bsdtar --options`, e.g.
+    /// `zip:ignorecrc32,hdrcharset=CP932,iso9660:!rockridge`.
+    pub fn set_options(self, options: &str) -> Result<Self, ArchiveError> {
+        let c_options = CString::new(options).unwrap();
+        unsafe {
+            let res = archive_read_set_options(self.handler, c_options.as_ptr());
+            self.option_result(res)
+        }
+    }
+
+    /// Sets the charset of the pathnames in the headers, e.g. `CP932` or
+    /// `CP866`. libarchive converts them to the charset of the current
+    /// locale, so the program needs a UTF-8 locale (see `setlocale`) for
+    /// them to come out as UTF-8, as they fail to convert in the `C` locale.
+    pub fn set_hdrcharset(self, charset: &str) -> Result<Self, ArchiveError> {
+        self.set_option(ReadOption::HdrCharset(charset))
+    }
+
+    // an option which is not used by any module only warns, but it still did not apply
+    unsafe fn option_result(self, res: c_int) -> Result<Self, ArchiveError> {
+        if res == ARCHIVE_OK {
+            Ok(self)
+        } else {
+            Err(archive_error(self.handler, res))
+        }
+    }
+
+    pub fn open_filename(self, fileName: &str, bufferSize: usize) -> Result<Self, ArchiveError> {
+        let fname = CString::new(fileName).unwrap();
+        unsafe {
+            let res = archive_read_open_filename(self.handler, fname.as_ptr(), bufferSize);
+            self.check(res).map(|()| self)
+        }
+    }
+
+    /// Reads the archive from memory, which is borrowed for as long as the
+    /// reader exists.
+    pub fn open_memory(self, memory: &'a [u8]) -> Result<Self, ArchiveError> {
+        unsafe {
+            // libarchive never writes to the buffer
+            let res = archive_read_open_memory(
+                self.handler,
+                memory.as_ptr() as *mut c_void,
+                memory.len(),
+            );
+            self.check(res).map(|()| self)
+        }
+    }
+
+    /// Reads the archive from memory the reader takes ownership of, e.g. a
+    /// `Vec<u8>`, `Box<[u8]>` or `Arc<[u8]>`.
+    pub fn open_memory_owned<T: AsRef<[u8]> + Any + Send>(
+        mut self,
+        memory: T,
+    ) -> Result<Self, ArchiveError> {
+        let memory = Box::new(memory);
+        let (memptr, len) = {
+            let slice = (*memory).as_ref();
+            (slice.as_ptr(), slice.len())
+        };
+        self.callback_data.push(memory);
+        unsafe {
+            let res = archive_read_open_memory(self.handler, memptr as *mut c_void, len);
+            self.check(res).map(|()| self)
+        }
+    }
+
+    /// Reads the archive from a file descriptor, e.g. a pipe or an inherited
+    /// socket. The descriptor is borrowed and not closed by the reader.
+    #[cfg(unix)]
+    pub fn open_fd<F: AsRawFd + Sync + ?Sized>(
+        self,
+        fd: &'a F,
+        bufferSize: usize,
+    ) -> Result<Self, ArchiveError> {
+        unsafe {
+            let res = archive_read_open_fd(self.handler, fd.as_raw_fd(), bufferSize);
+            self.check(res).map(|()| self)
+        }
+    }
+
+    /// Opens several files as one continuous archive, e.g. the volumes of a
+    /// split archive in order. Fails if there are none.
+    pub fn open_filenames<P: AsRef<Path>>(
+        self,
+        fileNames: &[P],
+        bufferSize: usize,
+    ) -> Result<Self, ArchiveError> {
+        if fileNames.is_empty() {
+            // libarchive reads stdin when given no names
+            return Err(usage_error("no archive to open"));
+        }
+        let fnames: Vec<CString> = fileNames
+            .iter()
+            .map(|name| path_to_cstring(name.as_ref()))
+            .collect();
+        let mut fname_ptrs: Vec<*const c_char> = fnames.iter().map(|name| name.as_ptr()).collect();
+        fname_ptrs.push(ptr::null());
+        unsafe {
+            let res =
+                archive_read_open_filenames(self.handler, fname_ptrs.as_mut_ptr(), bufferSize);
+            self.check(res).map(|()| self)
+        }
+    }
+
+    pub fn open_stream<T: Any + Read + Send>(self, source: T) -> Result<Self, ArchiveError> {
+        self.open_sources(vec![StreamSource::Plain(Box::new(source))])
+    }
+
+    /// Opens a stream which can also seek, so that skipping over entry data
+    /// becomes a seek and formats like zip and 7z can use their central directory.
+    pub fn open_seekable_stream<T: Any + Read + Seek + Send>(
+        self,
+        source: T,
+    ) -> Result<Self, ArchiveError> {
+        self.open_sources(vec![StreamSource::Seekable(Box::new(source))])
+    }
+
+    /// Reads several streams one after another as one continuous archive.
+    /// Fails if there are none.
+    pub fn open_streams<T, I>(self, sources: I) -> Result<Self, ArchiveError>
+    where
+        T: Any + Read + Send,
+        I: IntoIterator<Item = T>,
+    {
+        let sources = sources
+            .into_iter()
+            .map(|source| StreamSource::Plain(Box::new(source)) as StreamSource)
+            .collect();
+        self.open_sources(sources)
+    }
+
+    /// Like `open_streams`, for volumes which can seek, e.g. a split 7z archive.
+    pub fn open_seekable_streams<T, I>(self, sources: I) -> Result<Self, ArchiveError>
+    where
+        T: Any + Read + Seek + Send,
+        I: IntoIterator<Item = T>,
+    {
+        let sources = sources
+            .into_iter()
+            .map(|source| StreamSource::Seekable(Box::new(source)) as StreamSource)
+            .collect();
+        self.open_sources(sources)
+    }
+
+    fn open_sources(mut self, sources: Vec<StreamSource>) -> Result<Self, ArchiveError> {
+        if sources.is_empty() {
+            return Err(usage_error("no archive to open"));
+        }
+        unsafe {
+            let seekable = sources.iter().all(|source| match *source {
+                StreamSource::Plain(_) => false,
+                StreamSource::Seekable(_) => true,
+            });
+            archive_read_set_read_callback(self.handler, Some(arch_read));
+            archive_read_set_close_callback(self.handler, Some(arch_close));
+            archive_read_set_switch_callback(self.handler, Some(arch_switch));
+            if seekable {
+                archive_read_set_skip_callback(self.handler, Some(arch_skip));
+                archive_read_set_seek_callback(self.handler, Some(arch_seek));
+            }
+            for source in sources {
+                let mut rc = Box::new(ReadContainer::new(source));
+                archive_read_append_callback_data(
+                    self.handler,
+                    &mut *rc as *mut ReadContainer as *mut c_void,
+                );
+                self.callback_data.push(rc);
+            }
+            let res = archive_read_open1(self.handler);
+            self.check(res).map(|()| self)
+        }
+    }
+
+    /// The detected format, known once the first header has been read.
+    pub fn format(&self) -> Option<DetectedFormat> {
+        unsafe {
+            match archive_format(self.handler) {
+                0 => None,
+                code => Some(code_to_format(code)),
+            }
+        }
+    }
+
+    /// Describes the detected format in more detail, e.g. `GNU tar format`.
+    pub fn format_name(&self) -> Option<String> {
+        unsafe { wrap_to_option_string(archive_format_name(self.handler)) }
+    }
+
+    /// The detected compression filters, outermost last, e.g. `[Gzip, Uu]`
+    /// for a uuencoded tar.gz. The raw input itself is not listed.
+    pub fn filters(&self) -> Vec<FilterInfo> {
+        unsafe {
+            let count = archive_filter_count(self.handler);
+            (0..count)
+                .map(|n| FilterInfo {
+                    filter: code_to_filter(archive_filter_code(self.handler, n)),
+                    name: wrap_to_option_string(archive_filter_name(self.handler, n))
+                        .unwrap_or_default(),
+                    bytes: archive_filter_bytes(self.handler, n),
+                })
+                .filter(|info| info.filter != DetectedFilter::None)
+                .collect()
+        }
+    }
+
+    /// Bytes read from the input so far.
+    pub fn compressed_bytes(&self) -> i64 {
+        unsafe { archive_position_compressed(self.handler) }
+    }
+
+    /// Bytes which came out of the filters so far.
+    pub fn uncompressed_bytes(&self) -> i64 {
+        unsafe { archive_position_uncompressed(self.handler) }
+    }
+
+    /// Number of headers read so far.
+    pub fn file_count(&self) -> i32 {
+        unsafe { archive_file_count(self.handler) }
+    }
+
+    /// Closes the archive. Dropping the reader closes it as well, but
+    /// ignores errors.
+    pub fn close(self) -> Result<Stats, ArchiveError> {
+        unsafe {
+            let res = archive_read_close(self.handler);
+            self.check(res)?;
+        }
+        Ok(Stats {
+            compressed_bytes: self.compressed_bytes(),
+            uncompressed_bytes: self.uncompressed_bytes(),
+            file_count: self.file_count(),
+            warnings: self.take_warnings(),
+        })
+    }
+
+    /// Takes the warnings recorded by calls which succeeded nevertheless.
+    pub fn take_warnings(&self) -> Vec<ArchiveError> {
+        self.warnings.borrow_mut().split_off(0)
+    }
+
+    /// Makes `next_header` and `next_entry` skip over entries whose header could
+    /// not be read but which libarchive can continue after or retry, as
+    /// happens in damaged archives. The skipped entries are recorded instead, see
+    /// `take_failed_entries`, once for each entry even while libarchive
+    /// searches for the next header. Reading stops only at a fatal error or
+    /// the end.
+    pub fn skip_failed_entries(mut self) -> Self {
+        self.failed_entries = Some(Vec::new());
+        self
+    }
+
+    /// Takes the entries skipped since the last call.
+    pub fn take_failed_entries(&mut self) -> Vec<FailedEntry> {
+        match self.failed_entries {
+            Some(ref mut failed) => failed.split_off(0),
+            None => Vec::new(),
+        }
+    }
+
+    unsafe fn check(&self, res: c_int) -> Result<(), ArchiveError> {
+        check_callbacks(self.handler, &self.warnings, &self.callback_data, res)
+    }
+
+    unsafe fn error(&self, res: c_int) -> ArchiveError {
+        match self.check(res) {
+            Err(err) => err,
+            Ok(()) => archive_error(self.handler, res),
+        }
+    }
+
+    /// Reads the header of the next entry. The entry borrows the reader, so
+    /// it can not be used any more once the next header is read.
+    pub fn next_header(&mut self) -> Result<Entry<'_>, ArchiveError> {
+        let entry = self.read_next_header()?;
+        Ok(Entry {
+            entry,
+            reader: self,
+        })
+    }
+
+    fn read_next_header(&mut self) -> Result<*mut Struct_archive_entry, ArchiveError> {
+        unsafe {
+            let mut entry: *mut Struct_archive_entry = ptr::null_mut();
+            let mut res = archive_read_next_header(self.handler, &mut entry);
+            // libarchive retries until it finds the next header again, which
+            // is all one damaged entry
+            let mut resyncing = false;
+            while res == ARCHIVE_FAILED || res == ARCHIVE_RETRY {
+                match self.failed_entries {
+                    Some(ref mut failed) => {
+                        if !(resyncing && res == ARCHIVE_RETRY) {
+                            failed.push(FailedEntry {
+                                pathname: if entry.is_null() {
+                                    None
+                                } else {
+                                    wrap_to_option_string(archive_entry_pathname(entry))
+                                },
+                                error: archive_error(self.handler, res),
+                            });
+                        }
+                        resyncing = res == ARCHIVE_RETRY;
+                    }
+                    None => break,
+                }
+                res = archive_read_next_header(self.handler, &mut entry);
+            }
+            self.check(res)?;
+            Ok(entry)
+        }
+    }
+
+    /// Reads the next entry like `next_header`, but returns `None` at the end
+    /// of the archive. A fatal error is returned once and ends the archive.
+    /// As entries borrow the reader this is not an `Iterator`; use it as
+    /// `while let Some(entry) = reader.next_entry() { ... }`.
+    pub fn next_entry(&mut self) -> Option<Result<Entry<'_>, ArchiveError>> {
+        if self.done {
+            return None;
+        }
+        match self.read_next_header() {
+            Ok(entry) => Some(Ok(Entry {
+                entry,
+                reader: self,
+            })),
+            Err(ref err) if err.code() == ErrorCode::Eof => {
+                self.done = true;
+                None
+            }
+            Err(err) => {
+                if err.code() == ErrorCode::Fatal {
+                    self.done = true;
+                }
+                Some(Err(err))
+            }
+        }
+    }
+
+    /// Returns a `std::io::Read` over the data of the current entry.
+    pub fn data_reader(&self) -> DataReader<'_> {
+        DataReader { reader: self }
+    }
+
+    /// Reads the next block of the current entry without copying it. Returns
+    /// the offset of the block within the entry together with libarchive's
+    /// internal buffer, which stays valid until the next call on this reader.
+    /// Gaps between consecutive offsets are holes in a sparse entry.
+    /// `Ok(None)` is returned once the entry has no more data.
+    pub fn read_data_block(&mut self) -> Result<Option<(i64, &[u8])>, ArchiveError> {
+        unsafe {
+            let mut buff: *const c_void = ptr::null();
+            let mut size: size_t = 0;
+            let mut offset: i64 = 0;
+            let res = archive_read_data_block(self.handler, &mut buff, &mut size, &mut offset);
+            if res == ARCHIVE_EOF {
+                return Ok(None);
+            }
+            self.check(res)?;
+            if size == 0 {
+                Ok(Some((offset, &[])))
+            } else {
+                let block = std::slice::from_raw_parts(buff as *const u8, size);
+                Ok(Some((offset, block)))
+            }
+        }
+    }
+
+    pub fn read_data(&self, size: size_t) -> Result<Vec<u8>, ArchiveError> {
+        unsafe {
+            let mut chunk_vec = Vec::with_capacity(size);
+            let chunk_ptr = chunk_vec.as_mut_ptr();
+            let mut res = archive_read_data(self.handler, chunk_ptr as *mut c_void, size);
+            while res == ARCHIVE_WARN as ssize_t {
+                self.check(ARCHIVE_WARN)?;
+                res = archive_read_data(self.handler, chunk_ptr as *mut c_void, size);
+            }
+            if res < 0 {
+                Err(self.error(res as c_int))
+            } else if res == 0 {
+                Err(archive_error(self.handler, ARCHIVE_EOF))
+            } else {
+                chunk_vec.set_len(res as usize);
+                Ok(chunk_vec)
+            }
+        }
     }
 }
 
-#[derive(PartialEq, Clone)]
+pub struct DataReader<'a> {
+    reader: &'a Reader<'a>,
+}
+
+impl<'a> Read for DataReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        unsafe {
+            let handler = self.reader.handler;
+            let mut res = archive_read_data(handler, buf.as_mut_ptr() as *mut c_void, buf.len());
+            while res == ARCHIVE_WARN as ssize_t {
+                self.reader.check(ARCHIVE_WARN)?;
+                res = archive_read_data(handler, buf.as_mut_ptr() as *mut c_void, buf.len());
+            }
+            if res >= 0 {
+                Ok(res as usize)
+            } else {
+                Err(self.reader.error(res as c_int).into())
+            }
+        }
+    }
+}
+
+impl<'a> Drop for Reader<'a> {
+    fn drop(&mut self) {
+        unsafe {
+            archive_read_close(self.handler);
+            archive_read_free(self.handler);
+        }
+    }
+}
+
+/// Writes an archive, which it owns and frees when dropped.
 pub struct Writer {
-    handler: Rc<*mut Struct_archive>,
-    outUsed: Rc<*mut size_t>,
+    handler: *mut Struct_archive,
+    callback_data: CallbackData,
+    warnings: Warnings,
+    // created by the first `append_path`
+    disk: Option<DiskReader>,
 }
 
+// see `Reader`
+unsafe impl Send for Writer {}
+
 impl Drop for Writer {
     fn drop(&mut self) {
-        if Rc::strong_count(&self.handler) <= 1 {
-            unsafe {
-                archive_write_close(*self.handler);
-                archive_write_free(*self.handler);
-            }
+        // closes the archive unless it is closed already or was aborted,
+        // which must not write the trailer
+        unsafe {
+            archive_write_free(self.handler);
         }
     }
 }
@@ -392,11 +1546,11 @@ impl Writer {
             if h.is_null() {
                 Err(AllocationError)
             } else {
-                let mut init_used: Box<size_t> = Box::new(0);
-                let outUsed: *mut size_t = &mut *init_used;
                 Ok(Writer {
-                    handler: Rc::new(h),
-                    outUsed: Rc::new(outUsed),
+                    handler: h,
+                    callback_data: CallbackData::default(),
+                    warnings: Warnings::default(),
+                    disk: None,
                 })
             }
         }
@@ -404,14 +1558,14 @@ impl Writer {
     pub fn add_filter(self, filter: ArchiveFilter) -> Self {
         unsafe {
             match filter {
-                ArchiveFilter::Bzip2 => archive_write_add_filter_bzip2(*self.handler),
-                ArchiveFilter::Compress => archive_write_add_filter_compress(*self.handler),
-                ArchiveFilter::Gzip => archive_write_add_filter_gzip(*self.handler),
-                ArchiveFilter::Lzip => archive_write_add_filter_lzip(*self.handler),
-                ArchiveFilter::Lzma => archive_write_add_filter_lzma(*self.handler),
-                ArchiveFilter::None => archive_write_add_filter_none(*self.handler),
+                ArchiveFilter::Bzip2 => archive_write_add_filter_bzip2(self.handler),
+                ArchiveFilter::Compress => archive_write_add_filter_compress(self.handler),
+                ArchiveFilter::Gzip => archive_write_add_filter_gzip(self.handler),
+                ArchiveFilter::Lzip => archive_write_add_filter_lzip(self.handler),
+                ArchiveFilter::Lzma => archive_write_add_filter_lzma(self.handler),
+                ArchiveFilter::None => archive_write_add_filter_none(self.handler),
                 // TODO : Program(&str)
-                ArchiveFilter::Xz => archive_write_add_filter_xz(*self.handler),
+                ArchiveFilter::Xz => archive_write_add_filter_xz(self.handler),
             };
         }
         self
@@ -420,25 +1574,25 @@ impl Writer {
     pub fn set_format(self, format: ArchiveFormat) -> Self {
         unsafe {
             match format {
-                ArchiveFormat::_7Zip => archive_write_set_format_7zip(*self.handler),
-                ArchiveFormat::Ar_Bsd => archive_write_set_format_ar_bsd(*self.handler),
-                ArchiveFormat::Ar_Svr4 => archive_write_set_format_ar_svr4(*self.handler),
-                ArchiveFormat::Cpio => archive_write_set_format_cpio(*self.handler),
-                ArchiveFormat::Cpio_newc => archive_write_set_format_cpio_newc(*self.handler),
-                ArchiveFormat::Gnutar => archive_write_set_format_gnutar(*self.handler),
-                ArchiveFormat::Iso9600 => archive_write_set_format_iso9660(*self.handler),
-                ArchiveFormat::Mtree => archive_write_set_format_mtree(*self.handler),
-                // ArchiveFormat::Mtree_Classic => archive_write_set_format_mtree_classic(*self.handler),
-                ArchiveFormat::Pax => archive_write_set_format_pax(*self.handler),
+                ArchiveFormat::_7Zip => archive_write_set_format_7zip(self.handler),
+                ArchiveFormat::Ar_Bsd => archive_write_set_format_ar_bsd(self.handler),
+                ArchiveFormat::Ar_Svr4 => archive_write_set_format_ar_svr4(self.handler),
+                ArchiveFormat::Cpio => archive_write_set_format_cpio(self.handler),
+                ArchiveFormat::Cpio_newc => archive_write_set_format_cpio_newc(self.handler),
+                ArchiveFormat::Gnutar => archive_write_set_format_gnutar(self.handler),
+                ArchiveFormat::Iso9600 => archive_write_set_format_iso9660(self.handler),
+                ArchiveFormat::Mtree => archive_write_set_format_mtree(self.handler),
+                // ArchiveFormat::Mtree_Classic => archive_write_set_format_mtree_classic(self.handler),
+                ArchiveFormat::Pax => archive_write_set_format_pax(self.handler),
                 ArchiveFormat::Pax_Restricted => {
-                    archive_write_set_format_pax_restricted(*self.handler)
+                    archive_write_set_format_pax_restricted(self.handler)
                 }
-                ArchiveFormat::Shar => archive_write_set_format_shar(*self.handler),
-                ArchiveFormat::Shar_Dump => archive_write_set_format_shar_dump(*self.handler),
-                ArchiveFormat::Ustar => archive_write_set_format_ustar(*self.handler),
-                // ArchiveFormat::V7tar => archive_write_set_format_v7tar(*self.handler),
-                ArchiveFormat::Xar => archive_write_set_format_xar(*self.handler),
-                ArchiveFormat::Zip => archive_write_set_format_zip(*self.handler),
+                ArchiveFormat::Shar => archive_write_set_format_shar(self.handler),
+                ArchiveFormat::Shar_Dump => archive_write_set_format_shar_dump(self.handler),
+                ArchiveFormat::Ustar => archive_write_set_format_ustar(self.handler),
+                // ArchiveFormat::V7tar => archive_write_set_format_v7tar(self.handler),
+                ArchiveFormat::Xar => archive_write_set_format_xar(self.handler),
+                ArchiveFormat::Zip => archive_write_set_format_zip(self.handler),
             };
         }
         self
@@ -447,55 +1601,143 @@ impl Writer {
     pub fn set_compression(self, filter: ArchiveFilter) -> Self {
         unsafe {
             match filter {
-                ArchiveFilter::Bzip2 => archive_write_set_compression_bzip2(*self.handler),
-                ArchiveFilter::Compress => archive_write_set_compression_compress(*self.handler),
-                ArchiveFilter::Gzip => archive_write_set_compression_gzip(*self.handler),
-                ArchiveFilter::Lzip => archive_write_set_compression_lzip(*self.handler),
-                ArchiveFilter::Lzma => archive_write_set_compression_lzma(*self.handler),
-                ArchiveFilter::None => archive_write_set_compression_none(*self.handler),
-                ArchiveFilter::Xz => archive_write_set_compression_xz(*self.handler),
+                ArchiveFilter::Bzip2 => archive_write_set_compression_bzip2(self.handler),
+                ArchiveFilter::Compress => archive_write_set_compression_compress(self.handler),
+                ArchiveFilter::Gzip => archive_write_set_compression_gzip(self.handler),
+                ArchiveFilter::Lzip => archive_write_set_compression_lzip(self.handler),
+                ArchiveFilter::Lzma => archive_write_set_compression_lzma(self.handler),
+                ArchiveFilter::None => archive_write_set_compression_none(self.handler),
+                ArchiveFilter::Xz => archive_write_set_compression_xz(self.handler),
             };
         }
         self
     }
 
-    pub fn open_filename(&mut self, fileName: &str) -> Result<&mut Self, ArchiveError> {
-        let fname = CString::new(fileName).unwrap();
+    /// Sets the passphrase used to encrypt entries, see `set_zip_encryption`.
+    pub fn set_passphrase(self, passphrase: &str) -> Result<Self, ArchiveError> {
+        let c_passphrase = CString::new(passphrase).unwrap();
         unsafe {
-            let res = archive_write_open_filename(*self.handler, fname.as_ptr());
-            if res == ARCHIVE_OK {
-                Ok(self)
-            } else {
-                Err(code_to_error(res))
-            }
+            let res = archive_write_set_passphrase(self.handler, c_passphrase.as_ptr());
+            self.check(res).map(|()| self)
         }
     }
 
-    pub fn open_memory(&mut self, memory: &mut [u8]) -> Result<&mut Self, ArchiveError> {
+    /// Selects the encryption of zip entries; requires a passphrase.
+    pub fn set_zip_encryption(self, encryption: ZipEncryption) -> Result<Self, ArchiveError> {
+        let value = match encryption {
+            ZipEncryption::Traditional => "zipcrypt",
+            ZipEncryption::Aes128 => "aes128",
+            ZipEncryption::Aes256 => "aes256",
+        };
+        self.set_format_option(Some("zip"), "encryption", value)
+    }
+
+    /// Sets the charset pathnames and user and group names are written in,
+    /// e.g. `CP932`. Must follow `set_format`; not every format supports it.
+    pub fn set_hdrcharset(self, charset: &str) -> Result<Self, ArchiveError> {
+        self.set_format_option(None, "hdrcharset", charset)
+    }
+
+    fn set_format_option(
+        self,
+        module: Option<&str>,
+        name: &str,
+        value: &str,
+    ) -> Result<Self, ArchiveError> {
+        let c_module = module.map(|m| CString::new(m).unwrap());
+        let c_name = CString::new(name).unwrap();
+        let c_value = CString::new(value).unwrap();
         unsafe {
-            let memptr: *mut u8 = &mut memory[0];
-            let res = archive_write_open_memory(
-                *self.handler,
-                memptr as *mut c_void,
-                memory.len(),
-                *self.outUsed,
+            let res = archive_write_set_format_option(
+                self.handler,
+                c_module.as_ref().map_or(ptr::null(), |m| m.as_ptr()),
+                c_name.as_ptr(),
+                c_value.as_ptr(),
             );
+            // an option which is not used by any module only warns, but it did not apply
             if res == ARCHIVE_OK {
                 Ok(self)
             } else {
-                Err(code_to_error(res))
+                Err(archive_error(self.handler, res))
             }
         }
     }
 
-    pub fn write_header(&mut self, entry: ArchiveEntryReader) -> Result<&mut Self, ArchiveError> {
+    pub fn open_filename(&mut self, fileName: &str) -> Result<&mut Self, ArchiveError> {
+        let fname = CString::new(fileName).unwrap();
         unsafe {
-            let res = archive_write_header(*self.handler, entry.entry);
-            if res == ARCHIVE_OK {
-                Ok(self)
-            } else {
-                Err(code_to_error(res))
+            let res = archive_write_open_filename(self.handler, fname.as_ptr());
+            self.check(res).map(|()| self)
+        }
+    }
+
+    /// Writes the archive into a sink like a socket, a pipe or an upload.
+    /// Errors of the sink are returned by the writer's methods, and the sink
+    /// is given back by `StreamWriter::close`.
+    pub fn open_stream<W: Write + Send + 'static>(
+        mut self,
+        sink: W,
+    ) -> Result<StreamWriter<W>, ArchiveError> {
+        let mut wc = Box::new(WriteContainer {
+            sink: Box::new(sink),
+            failure: Cell::new(None),
+        });
+        let client_data = &mut *wc as *mut WriteContainer as *mut c_void;
+        self.callback_data.push(wc);
+        unsafe {
+            // like a regular file, and unlike a tape, the sink needs no padding
+            if archive_write_get_bytes_in_last_block(self.handler) < 0 {
+                archive_write_set_bytes_in_last_block(self.handler, 1);
             }
+            let res = archive_write_open(
+                self.handler,
+                client_data,
+                None,
+                Some(arch_write),
+                Some(arch_write_close),
+            );
+            self.check(res)?;
+        }
+        Ok(StreamWriter {
+            writer: self,
+            sink: PhantomData,
+        })
+    }
+
+    /// Writes the archive into a `Vec<u8>`, which grows as needed and is
+    /// returned by `StreamWriter::close`.
+    pub fn open_vec(self) -> Result<StreamWriter<Vec<u8>>, ArchiveError> {
+        self.open_stream(Vec::new())
+    }
+
+    /// Writes the archive into a fixed buffer, which is borrowed for as long
+    /// as the writer exists. Writing more than fits fails with a
+    /// "buffer too small" error of kind `WriteZero`.
+    pub fn open_memory(self, memory: &mut [u8]) -> Result<MemoryWriter<'_>, ArchiveError> {
+        let buffer = FixedBuffer {
+            memory: memory.as_mut_ptr(),
+            len: memory.len(),
+            used: 0,
+        };
+        Ok(MemoryWriter {
+            writer: self.open_stream(buffer)?,
+            memory: PhantomData,
+        })
+    }
+
+    /// Writes the header of an entry read from another archive.
+    pub fn write_header(&mut self, entry: &Entry) -> Result<&mut Self, ArchiveError> {
+        unsafe {
+            let res = archive_write_header(self.handler, entry.entry);
+            self.check(res).map(|()| self)
+        }
+    }
+
+    /// Writes the header of an entry built with `EntryBuilder`.
+    pub fn write_entry(&mut self, entry: &EntryBuilder) -> Result<&mut Self, ArchiveError> {
+        unsafe {
+            let res = archive_write_header(self.handler, entry.entry);
+            self.check(res).map(|()| self)
         }
     }
 
@@ -504,49 +1746,304 @@ impl Writer {
         pathname: &str,
         entry_size: i64,
     ) -> Result<&mut Self, ArchiveError> {
-        use ArchiveEntryIOType::*;
-        unsafe {
-            let new_entry = archive_entry_new();
-            archive_entry_set_perm(new_entry, 0o755);
-            archive_entry_set_size(new_entry, entry_size);
-            let entry = ArchiveEntryReader {
-                entry: new_entry,
-                handler: self.handler.clone(),
-                iotype: WriterEntry,
-            };
-            entry.set_filetype(ArchiveEntryFiletype::AE_IFREG);
-            entry.set_pathname(pathname);
+        let entry =
+            new_entry(pathname, ArchiveEntryFiletype::AE_IFREG, 0o755)?.set_size(entry_size);
+        self.write_entry(&entry)
+    }
+
+    /// Adds a directory with permissions 0755.
+    pub fn add_directory(&mut self, pathname: &str) -> Result<&mut Self, ArchiveError> {
+        let entry = new_entry(pathname, ArchiveEntryFiletype::AE_IFDIR, 0o755)?;
+        self.write_entry(&entry)
+    }
 
-            self.write_header(entry)
+    /// Adds a symlink pointing to `target`.
+    pub fn add_symlink(&mut self, pathname: &str, target: &str) -> Result<&mut Self, ArchiveError> {
+        let entry = new_entry(pathname, ArchiveEntryFiletype::AE_IFLNK, 0o777)?.set_symlink(target);
+        self.write_entry(&entry)
+    }
+
+    /// Adds a hardlink to `target`, an entry written before. Only tar and
+    /// shar store links by their target; other formats fail instead of
+    /// writing an empty file. cpio links entries by inode, see
+    /// `EntryBuilder::set_ino` and `set_nlink`.
+    pub fn add_hardlink(
+        &mut self,
+        pathname: &str,
+        target: &str,
+    ) -> Result<&mut Self, ArchiveError> {
+        unsafe {
+            let format = archive_format(self.handler) & ARCHIVE_FORMAT_BASE_MASK;
+            if format != ARCHIVE_FORMAT_TAR && format != ARCHIVE_FORMAT_SHAR {
+                let name = wrap_to_option_string(archive_format_name(self.handler));
+                let message = format!(
+                    "{}: {} format cannot archive hardlinks",
+                    pathname,
+                    name.as_deref().unwrap_or("this")
+                );
+                set_error_message(self.handler, ARCHIVE_ERRNO_FILE_FORMAT, &message);
+                self.check(ARCHIVE_FAILED)?;
+            }
         }
+        let entry =
+            new_entry(pathname, ArchiveEntryFiletype::AE_IFREG, 0o644)?.set_hardlink(target);
+        self.write_entry(&entry)
+    }
+
+    /// Adds a named pipe with permissions 0644. Formats without named pipes,
+    /// like zip, fail.
+    pub fn add_fifo(&mut self, pathname: &str) -> Result<&mut Self, ArchiveError> {
+        let entry = new_entry(pathname, ArchiveEntryFiletype::AE_IFIFO, 0o644)?;
+        self.write_entry(&entry)
+    }
+
+    /// Adds a device node with permissions 0600. Formats without device
+    /// nodes, like zip, fail.
+    pub fn add_device(
+        &mut self,
+        pathname: &str,
+        kind: DeviceKind,
+        major: u64,
+        minor: u64,
+    ) -> Result<&mut Self, ArchiveError> {
+        let filetype = match kind {
+            DeviceKind::Character => ArchiveEntryFiletype::AE_IFCHR,
+            DeviceKind::Block => ArchiveEntryFiletype::AE_IFBLK,
+        };
+        let entry = new_entry(pathname, filetype, 0o600)?.set_rdev(major, minor);
+        self.write_entry(&entry)
     }
 
     pub fn write_data(&mut self, data: Vec<u8>) -> Result<&mut Self, ArchiveError> {
+        self.write_bytes(&data)?;
+        Ok(self)
+    }
+
+    fn write_bytes(&mut self, data: &[u8]) -> Result<(), ArchiveError> {
         unsafe {
-            let data_len = data.len();
-            let data_bytes = CString::from_vec_unchecked(data);
-            // TODO: How to handle errors here?
-            archive_write_data(*self.handler, data_bytes.as_ptr() as *mut c_void, data_len);
+            let res = archive_write_data(self.handler, data.as_ptr() as *mut c_void, data.len());
+            if res < 0 {
+                self.check(res as c_int)?;
+            }
         }
-        Ok(self)
+        Ok(())
+    }
+
+    /// Adds the file `src` from the disk as `pathname`, with the ownership,
+    /// times, extended attributes, ACLs and file flags libarchive reads from
+    /// it. Symlinks are added as symlinks, and the data of regular files is
+    /// copied in chunks.
+    pub fn append_path(
+        &mut self,
+        src: impl AsRef<Path>,
+        pathname: &str,
+    ) -> Result<&mut Self, ArchiveError> {
+        let src = src.as_ref();
+        if self.disk.is_none() {
+            self.disk = Some(DiskReader::new()?);
+        }
+        let disk = self.disk.as_ref().unwrap().handler;
+        let metadata =
+            fs::symlink_metadata(src).map_err(|err| io_archive_error(ARCHIVE_FAILED, &err))?;
+        // opened before libarchive reads the metadata, so that both are of the same file
+        let file = if metadata.is_file() {
+            Some(File::open(src).map_err(|err| io_archive_error(ARCHIVE_FAILED, &err))?)
+        } else {
+            None
+        };
+        #[cfg(unix)]
+        let fd = file.as_ref().map_or(-1, |file| file.as_raw_fd());
+        #[cfg(not(unix))]
+        let fd = -1;
+        let entry = alloc_entry()?;
+        let size = unsafe {
+            copy_sourcepath(entry.entry, src);
+            let res = archive_read_disk_entry_from_file(disk, entry.entry, fd, ptr::null());
+            check_result(disk, &self.warnings, res)?;
+            archive_entry_size(entry.entry)
+        };
+        self.write_entry(&entry.set_pathname(pathname))?;
+        if let Some(file) = file {
+            // a file growing meanwhile is cut at the size in the header
+            let mut data = file.take(size as u64);
+            let mut buffer = vec![0; 64 * 1024];
+            loop {
+                match data.read(&mut buffer) {
+                    Ok(0) => break,
+                    Ok(len) => self.write_bytes(&buffer[..len])?,
+                    Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+                    Err(err) => return Err(io_archive_error(ARCHIVE_FATAL, &err)),
+                }
+            }
+        }
+        self.write_finish_entry()
+    }
+
+    unsafe fn check(&self, res: c_int) -> Result<(), ArchiveError> {
+        check_callbacks(self.handler, &self.warnings, &self.callback_data, res)
     }
+
+    // the sink of `open_stream`
+    fn sink<W: 'static>(&self) -> &W {
+        self.callback_data
+            .iter()
+            .find_map(|data| data.downcast_ref::<WriteContainer>())
+            .and_then(|wc| (*wc.sink).as_any().downcast_ref::<W>())
+            .expect("opened with this sink")
+    }
+
+    // the sink of `open_stream`, replaced by a dummy
+    fn take_sink<W: 'static>(&mut self) -> W {
+        let wc = self
+            .callback_data
+            .iter_mut()
+            .find_map(|data| data.downcast_mut::<WriteContainer>())
+            .expect("opened with open_stream");
+        let sink = std::mem::replace(&mut wc.sink, Box::new(io::sink()));
+        *sink
+            .into_any()
+            .downcast::<W>()
+            .expect("opened with this sink")
+    }
+
+    /// Takes the warnings recorded by calls which succeeded nevertheless.
+    pub fn take_warnings(&self) -> Vec<ArchiveError> {
+        self.warnings.borrow_mut().split_off(0)
+    }
+
     pub fn write_finish_entry(&mut self) -> Result<&mut Self, ArchiveError> {
         unsafe {
-            let res = archive_write_finish_entry(*self.handler);
-            if res == ARCHIVE_OK {
+            let res = archive_write_finish_entry(self.handler);
+            self.check(res).map(|()| self)
+        }
+    }
+
+    /// Closes the archive, which writes its trailer and flushes the filters.
+    /// Dropping the writer closes it as well, but ignores errors like a full
+    /// disk, which leave a truncated archive behind.
+    pub fn close(mut self) -> Result<Stats, ArchiveError> {
+        self.close_archive()
+    }
+
+    fn close_archive(&mut self) -> Result<Stats, ArchiveError> {
+        unsafe {
+            let res = archive_write_close(self.handler);
+            self.check(res)?;
+            Ok(Stats {
+                compressed_bytes: archive_filter_bytes(self.handler, -1),
+                uncompressed_bytes: archive_filter_bytes(self.handler, 0),
+                file_count: archive_file_count(self.handler),
+                warnings: self.take_warnings(),
+            })
+        }
+    }
+
+    /// Gives up on the archive, so that no trailer is written and the output
+    /// is left incomplete.
+    pub fn abort(self) {
+        unsafe {
+            archive_write_fail(self.handler);
+        }
+    }
+}
+
+/// A `Writer` into a sink, see `Writer::open_stream`. It writes the entries
+/// with the methods of `Writer`, but can not be reopened or swapped for
+/// another writer.
+pub struct StreamWriter<W> {
+    writer: Writer,
+    sink: PhantomData<W>,
+}
+
+// the methods of `Writer` for writing entries
+macro_rules! forward_write {
+    ( $( $fname:ident ( $( $arg:ident : $argty:ty ),* ); )* ) => {
+        $(
+            /// Like the method of `Writer` of the same name.
+            pub fn $fname(&mut self, $( $arg: $argty ),*) -> Result<&mut Self, ArchiveError> {
+                self.writer.$fname($( $arg ),*)?;
                 Ok(self)
-            } else {
-                Err(code_to_error(res))
             }
+        )*
+
+        /// Takes the warnings recorded by calls which succeeded nevertheless.
+        pub fn take_warnings(&self) -> Vec<ArchiveError> {
+            self.writer.take_warnings()
+        }
+    };
+}
+
+macro_rules! writer_methods {
+    () => {
+        forward_write! {
+            write_header(entry: &Entry<'_>);
+            write_entry(entry: &EntryBuilder);
+            write_header_new(pathname: &str, entry_size: i64);
+            write_data(data: Vec<u8>);
+            write_finish_entry();
+            add_directory(pathname: &str);
+            add_symlink(pathname: &str, target: &str);
+            add_hardlink(pathname: &str, target: &str);
+            add_fifo(pathname: &str);
+            add_device(pathname: &str, kind: DeviceKind, major: u64, minor: u64);
+            append_path(src: impl AsRef<Path>, pathname: &str);
         }
+    };
+}
+
+impl<W: 'static> StreamWriter<W> {
+    writer_methods!();
+
+    /// Closes the archive like `Writer::close` and gives back the sink, which
+    /// has been flushed. The sink is given back with the error as well.
+    pub fn close(mut self) -> Result<(Stats, W), (ArchiveError, W)> {
+        match self.writer.close_archive() {
+            Ok(stats) => Ok((stats, self.writer.take_sink())),
+            Err(err) => Err((err, self.writer.take_sink())),
+        }
+    }
+
+    /// Gives up on the archive like `Writer::abort` and gives back the sink.
+    pub fn abort(mut self) -> W {
+        unsafe {
+            archive_write_fail(self.writer.handler);
+        }
+        self.writer.take_sink()
+    }
+}
+
+/// A `Writer` into a fixed buffer, see `Writer::open_memory`. It writes the
+/// entries with the methods of `Writer`.
+pub struct MemoryWriter<'m> {
+    writer: StreamWriter<FixedBuffer>,
+    memory: PhantomData<&'m mut [u8]>,
+}
+
+impl<'m> MemoryWriter<'m> {
+    writer_methods!();
+
+    /// Bytes of the buffer written so far. libarchive writes in blocks, so
+    /// this lags behind until the archive is closed.
+    pub fn used(&self) -> usize {
+        self.writer.writer.sink::<FixedBuffer>().used
+    }
+
+    /// Closes the archive like `Writer::close` and returns the number of
+    /// bytes of the buffer it takes up.
+    pub fn close(self) -> Result<(Stats, usize), ArchiveError> {
+        let (stats, buffer) = self.writer.close().map_err(|(err, _)| err)?;
+        Ok((stats, buffer.used))
     }
 }
 
-#[derive(PartialEq, Clone)]
 pub struct WriterToDisk {
-    handler: Rc<*mut Struct_archive>,
+    handler: *mut Struct_archive,
+    warnings: Warnings,
 }
 
+// see `Reader`
+unsafe impl Send for WriterToDisk {}
+
 impl WriterToDisk {
     pub fn new() -> Result<WriterToDisk, AllocationError> {
         unsafe {
@@ -555,87 +2052,333 @@ impl WriterToDisk {
                 Err(AllocationError)
             } else {
                 Ok(WriterToDisk {
-                    handler: Rc::new(h),
+                    handler: h,
+                    warnings: Warnings::default(),
                 })
             }
         }
     }
+
+    /// Closes the writer, which sets the times and permissions of
+    /// directories, as these are deferred until all entries are written.
+    pub fn close(self) -> Result<Stats, ArchiveError> {
+        unsafe {
+            let res = archive_write_close(self.handler);
+            check_result(self.handler, &self.warnings, res)?;
+            // the disk writer counts no bytes, and libarchive crashes when asked for them
+            Ok(Stats {
+                compressed_bytes: 0,
+                uncompressed_bytes: 0,
+                file_count: archive_file_count(self.handler),
+                warnings: self.warnings.borrow_mut().split_off(0),
+            })
+        }
+    }
 }
 
 impl Drop for WriterToDisk {
     fn drop(&mut self) {
-        if Rc::strong_count(&self.handler) <= 1 {
-            unsafe {
-                archive_write_close(*self.handler);
-                archive_write_free(*self.handler);
-            }
+        unsafe {
+            archive_write_close(self.handler);
+            archive_write_free(self.handler);
         }
     }
 }
 
-pub struct ArchiveEntryReader {
+/// An entry of a `Reader`. Its metadata belongs to the reader and is only
+/// valid until the next header is read, so the entry borrows the reader.
+/// Reading from the entry reads its data.
+pub struct Entry<'r> {
     entry: *mut Struct_archive_entry,
-    handler: Rc<*mut Struct_archive>,
-    iotype: ArchiveEntryIOType,
+    reader: &'r Reader<'r>,
 }
 
 macro_rules! get_time {
     ( $fname:ident, $apiname:ident) => {
-        pub fn $fname(&self) -> Duration {
+        /// `None` if the time is not set, e.g. as the format does not store it.
+        pub fn $fname(&self) -> Option<SystemTime> {
             unsafe {
+                if ${concat(archive_entry_, $apiname, _is_set)}(self.entry) == 0 {
+                    return None;
+                }
                 let sec = (${concat(archive_entry_, $apiname)})(self.entry);
                 let nsec = (${concat(archive_entry_, $apiname, _nsec)})(self.entry);
-                Duration::new(sec, nsec as i32)
+                Some(to_system_time(sec, nsec))
+            }
+        }
+    };
+}
+
+macro_rules! set_time {
+    ( $fname:ident, $apiname:ident) => {
+        pub fn ${concat(set_, $fname)}(&mut self, time: SystemTime) {
+            let (sec, nsec) = from_system_time(time);
+            unsafe {
+                ${concat(archive_entry_set_, $apiname)}(self.entry, sec, nsec);
+            }
+        }
+
+        pub fn ${concat(unset_, $fname)}(&mut self) {
+            unsafe {
+                ${concat(archive_entry_unset_, $apiname)}(self.entry);
+            }
+        }
+    };
+}
+
+// time_t is 32 bits wide on some platforms
+#[allow(clippy::unnecessary_cast)]
+fn to_system_time(sec: time_t, nsec: c_long) -> SystemTime {
+    let nsec = Duration::from_nanos(nsec as u64);
+    if sec >= 0 {
+        UNIX_EPOCH + Duration::from_secs(sec as u64) + nsec
+    } else {
+        UNIX_EPOCH - Duration::from_secs(sec.unsigned_abs() as u64) + nsec
+    }
+}
+
+// libarchive keeps the nanoseconds positive, also before the epoch
+fn from_system_time(time: SystemTime) -> (time_t, c_long) {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(after) => (after.as_secs() as time_t, after.subsec_nanos() as c_long),
+        Err(err) => {
+            let before = err.duration();
+            let sec = -(before.as_secs() as time_t);
+            match before.subsec_nanos() {
+                0 => (sec, 0),
+                nsec => (sec - 1, (1_000_000_000 - nsec) as c_long),
+            }
+        }
+    }
+}
+
+macro_rules! get_name {
+    ( $fname:ident ) => {
+        pub fn $fname(&self) -> Option<String> {
+            unsafe { wrap_to_string(${concat(archive_entry_, $fname)}(self.entry)) }
+        }
+
+        pub fn ${concat($fname, _w)}(&self) -> Option<String> {
+            unsafe { wrap_to_wide_string(${concat(archive_entry_, $fname, _w)}(self.entry)) }
+        }
+    };
+}
+
+macro_rules! set_name {
+    ( $apiname:ident ) => {
+        /// Stores the name as UTF-8, which formats like pax and zip write as
+        /// is. Returns `false` if it contains a NUL, or if it could not also
+        /// be converted to the current locale, in which case the UTF-8 bytes
+        /// are stored as they are.
+        pub fn ${concat(set_, $apiname, _utf8)}(&mut self, name: &str) -> bool {
+            match CString::new(name) {
+                Ok(c_name) => unsafe {
+                    update_name_utf8(
+                        self.entry,
+                        &c_name,
+                        ${concat(archive_entry_update_, $apiname, _utf8)},
+                        ${concat(archive_entry_copy_, $apiname)},
+                    )
+                },
+                Err(_) => false,
+            }
+        }
+
+        /// Stores the name as a wide string, which is converted to the
+        /// charset of the archive when written. Returns `false` if it contains a NUL.
+        pub fn ${concat(set_, $apiname, _w)}(&mut self, name: &str) -> bool {
+            match to_wide(name) {
+                Some(wide) => unsafe {
+                    ${concat(archive_entry_copy_, $apiname, _w)}(self.entry, wide.as_ptr());
+                    true
+                },
+                None => false,
             }
         }
     };
 }
 
-unsafe fn wrap_to_string(ptr: *const c_char) -> String {
-    let path = CStr::from_ptr(ptr);
-    String::from(std::str::from_utf8(path.to_bytes()).unwrap())
+// Writers crash on names that only have a UTF-8 form, so the raw bytes are
+// kept when they cannot be converted to the locale.
+unsafe fn update_name_utf8(
+    entry: *mut Struct_archive_entry,
+    name: &CStr,
+    update: unsafe extern "C" fn(*mut Struct_archive_entry, *const c_char) -> c_int,
+    copy: unsafe extern "C" fn(*mut Struct_archive_entry, *const c_char),
+) -> bool {
+    if update(entry, name.as_ptr()) != 0 {
+        true
+    } else {
+        copy(entry, name.as_ptr());
+        false
+    }
+}
+
+// libarchive turns an option off when it is given no value
+fn flag_value(on: bool) -> Option<&'static str> {
+    if on {
+        Some("1")
+    } else {
+        None
+    }
 }
 
-impl ArchiveEntryReader {
+unsafe fn wrap_to_option_string(ptr: *const c_char) -> Option<String> {
+    if ptr.is_null() {
+        None
+    } else {
+        Some(CStr::from_ptr(ptr).to_string_lossy().into_owned())
+    }
+}
+
+// `None` when the string is missing or not UTF-8, see `wrap_to_bytes` for the raw string
+unsafe fn wrap_to_string(ptr: *const c_char) -> Option<String> {
+    wrap_to_bytes(ptr).and_then(|bytes| String::from_utf8(bytes.to_vec()).ok())
+}
+
+unsafe fn wrap_to_bytes<'e>(ptr: *const c_char) -> Option<&'e [u8]> {
+    if ptr.is_null() {
+        None
+    } else {
+        Some(CStr::from_ptr(ptr).to_bytes())
+    }
+}
+
+unsafe fn wrap_to_wide<'e>(ptr: *const wchar_t) -> Option<&'e [wchar_t]> {
+    if ptr.is_null() {
+        return None;
+    }
+    let mut len = 0;
+    while *ptr.add(len) != 0 {
+        len += 1;
+    }
+    Some(std::slice::from_raw_parts(ptr, len))
+}
+
+// a NUL terminated wide string, `None` if it contains a NUL
+fn to_wide(s: &str) -> Option<Vec<wchar_t>> {
+    if s.contains('\0') {
+        return None;
+    }
+    #[cfg(windows)]
+    let mut wide: Vec<wchar_t> = s.encode_utf16().collect();
+    #[cfg(not(windows))]
+    let mut wide: Vec<wchar_t> = s.chars().map(|c| c as wchar_t).collect();
+    wide.push(0);
+    Some(wide)
+}
+
+// wchar_t is UTF-16 on Windows and UTF-32 elsewhere
+unsafe fn wrap_to_wide_string(ptr: *const wchar_t) -> Option<String> {
+    let wide = wrap_to_wide(ptr)?;
+    #[cfg(windows)]
+    {
+        String::from_utf16(wide).ok()
+    }
+    #[cfg(not(windows))]
+    {
+        wide.iter().map(|&c| char::from_u32(c as u32)).collect()
+    }
+}
+
+fn filetype_code(filetype: ArchiveEntryFiletype) -> c_uint {
+    match filetype {
+        ArchiveEntryFiletype::AE_IFREG => 0o100_000,
+        ArchiveEntryFiletype::AE_IFLNK => 0o120_000,
+        ArchiveEntryFiletype::AE_IFSOCK => 0o140_000,
+        ArchiveEntryFiletype::AE_IFCHR => 0o020_000,
+        ArchiveEntryFiletype::AE_IFBLK => 0o060_000,
+        ArchiveEntryFiletype::AE_IFDIR => 0o040_000,
+        ArchiveEntryFiletype::AE_IFIFO => 0o010_000,
+    }
+}
+
+impl<'r> Entry<'r> {
     pub fn size(&self) -> i64 {
         unsafe { archive_entry_size(self.entry) }
     }
 
-    pub fn pathname(&self) -> String {
+    /// The pathname, or `None` if the entry has none or it is not UTF-8;
+    /// see `pathname_bytes` and `path` for names in other encodings.
+    pub fn pathname(&self) -> Option<String> {
         unsafe { wrap_to_string(archive_entry_pathname(self.entry)) }
     }
 
-    pub fn sourcepath(&self) -> String {
+    /// The pathname as stored in the archive.
+    pub fn pathname_bytes(&self) -> Option<&[u8]> {
+        unsafe { wrap_to_bytes(archive_entry_pathname(self.entry)) }
+    }
+
+    /// The pathname converted by libarchive from the archive's charset
+    /// according to the current locale.
+    pub fn pathname_w(&self) -> Option<String> {
+        unsafe { wrap_to_wide_string(archive_entry_pathname_w(self.entry)) }
+    }
+
+    /// The pathname as a path of the platform, which keeps names that are
+    /// not UTF-8.
+    #[cfg(unix)]
+    pub fn path(&self) -> Option<PathBuf> {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+        self.pathname_bytes()
+            .map(|bytes| PathBuf::from(OsStr::from_bytes(bytes)))
+    }
+
+    /// The pathname as a path of the platform, which keeps names that are
+    /// not UTF-8.
+    #[cfg(windows)]
+    pub fn path(&self) -> Option<PathBuf> {
+        use std::ffi::OsString;
+        use std::os::windows::ffi::OsStringExt;
+        unsafe {
+            wrap_to_wide(archive_entry_pathname_w(self.entry))
+                .map(|wide| PathBuf::from(OsString::from_wide(wide)))
+        }
+    }
+
+    /// The path on disk the entry was read from, only set for entries of
+    /// the disk.
+    pub fn sourcepath(&self) -> Option<String> {
         unsafe { wrap_to_string(archive_entry_sourcepath(self.entry)) }
     }
 
-    pub fn set_filetype(&self, filetype: ArchiveEntryFiletype) {
-        let c_type = match filetype {
-            ArchiveEntryFiletype::AE_IFMT => 0o170_000,
-            ArchiveEntryFiletype::AE_IFREG => 0o100_000,
-            ArchiveEntryFiletype::AE_IFLNK => 0o120_000,
-            ArchiveEntryFiletype::AE_IFSOCK => 0o140_000,
-            ArchiveEntryFiletype::AE_IFCHR => 0o020_000,
-            ArchiveEntryFiletype::AE_IFBLK => 0o060_000,
-            ArchiveEntryFiletype::AE_IFDIR => 0o040_000,
-            ArchiveEntryFiletype::AE_IFIFO => 0o010_000,
-        };
+    pub fn sourcepath_bytes(&self) -> Option<&[u8]> {
+        unsafe { wrap_to_bytes(archive_entry_sourcepath(self.entry)) }
+    }
+
+    pub fn sourcepath_w(&self) -> Option<String> {
+        unsafe { wrap_to_wide_string(archive_entry_sourcepath_w(self.entry)) }
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        unsafe { archive_entry_is_encrypted(self.entry) != 0 }
+    }
+
+    pub fn is_data_encrypted(&self) -> bool {
+        unsafe { archive_entry_is_data_encrypted(self.entry) != 0 }
+    }
+
+    pub fn is_metadata_encrypted(&self) -> bool {
+        unsafe { archive_entry_is_metadata_encrypted(self.entry) != 0 }
+    }
+
+    pub fn set_filetype(&mut self, filetype: ArchiveEntryFiletype) {
         unsafe {
-            archive_entry_set_filetype(self.entry, c_type);
+            archive_entry_set_filetype(self.entry, filetype_code(filetype));
         }
     }
 
-    pub fn set_pathname(&self, pathname: &str) {
+    pub fn set_pathname(&mut self, pathname: &str) {
         let c_pathname = CString::new(pathname).unwrap();
         unsafe {
             archive_entry_set_pathname(self.entry, c_pathname.as_ptr());
         }
     }
 
-    pub fn archive(&self) -> Reader {
-        Reader {
-            handler: self.handler.clone(),
-        }
+    /// The reader the entry belongs to.
+    pub fn archive(&self) -> &'r Reader<'r> {
+        self.reader
     }
 
     pub fn extract_to(
@@ -651,17 +2394,274 @@ impl ArchiveEntryReader {
     }
     pub fn extract(self, flags: Vec<ArchiveExtractFlag>) -> Result<Self, ArchiveError> {
         unsafe {
-            let res = archive_read_extract(*self.handler, self.entry, flags_to_code(flags));
-            if res == ARCHIVE_OK {
-                Ok(self)
-            } else {
-                Err(code_to_error(res))
-            }
+            let handler = self.reader.handler;
+            let res = archive_read_extract(handler, self.entry, flags_to_code(flags));
+            self.reader.check(res).map(|()| self)
         }
     }
 
+    get_name!(uname);
+    get_name!(gname);
+    get_name!(hardlink);
+    get_name!(symlink);
+
+    set_name!(pathname);
+    set_name!(uname);
+    set_name!(gname);
+    set_name!(hardlink);
+    set_name!(symlink);
+    // the symlink for symlinks, the hardlink otherwise
+    set_name!(link);
+
     get_time!(access_time, atime);
     get_time!(creation_time, birthtime);
     get_time!(inode_change_time, ctime);
     get_time!(modification_time, mtime);
+
+    set_time!(access_time, atime);
+    set_time!(creation_time, birthtime);
+    set_time!(inode_change_time, ctime);
+    set_time!(modification_time, mtime);
+}
+
+impl<'r> Read for Entry<'r> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.data_reader().read(buf)
+    }
+}
+
+/// The metadata of an entry to write, see `Writer::write_entry`. Names are
+/// stored as with `Entry::set_pathname_utf8`; the setters panic if a name
+/// contains a NUL. The builder owns its entry and frees it when dropped.
+pub struct EntryBuilder {
+    entry: *mut Struct_archive_entry,
+}
+
+// the entry is plain data owned by the builder
+unsafe impl Send for EntryBuilder {}
+
+macro_rules! build_name {
+    ( $apiname:ident ) => {
+        pub fn ${concat(set_, $apiname)}(self, name: &str) -> Self {
+            let c_name = CString::new(name).unwrap();
+            unsafe {
+                update_name_utf8(
+                    self.entry,
+                    &c_name,
+                    ${concat(archive_entry_update_, $apiname, _utf8)},
+                    ${concat(archive_entry_copy_, $apiname)},
+                );
+            }
+            self
+        }
+    };
+}
+
+macro_rules! build_time {
+    ( $fname:ident, $apiname:ident) => {
+        pub fn ${concat(set_, $fname)}(self, time: SystemTime) -> Self {
+            let (sec, nsec) = from_system_time(time);
+            unsafe {
+                ${concat(archive_entry_set_, $apiname)}(self.entry, sec, nsec);
+            }
+            self
+        }
+    };
+}
+
+// dev_t and mode_t differ in width between platforms
+#[allow(clippy::unnecessary_cast)]
+impl EntryBuilder {
+    pub fn new() -> Result<EntryBuilder, AllocationError> {
+        unsafe {
+            let entry = archive_entry_new();
+            if entry.is_null() {
+                Err(AllocationError)
+            } else {
+                Ok(EntryBuilder { entry })
+            }
+        }
+    }
+
+    pub fn set_filetype(self, filetype: ArchiveEntryFiletype) -> Self {
+        unsafe {
+            archive_entry_set_filetype(self.entry, filetype_code(filetype));
+        }
+        self
+    }
+
+    /// Sets the file type and permissions at once, as in `st_mode`.
+    pub fn set_mode(self, mode: u32) -> Self {
+        unsafe {
+            archive_entry_set_mode(self.entry, mode as mode_t);
+        }
+        self
+    }
+
+    /// Sets the permissions, keeping the file type.
+    pub fn set_perm(self, perm: u32) -> Self {
+        unsafe {
+            archive_entry_set_perm(self.entry, perm as mode_t);
+        }
+        self
+    }
+
+    /// The size of the data written after the header, 0 for anything but
+    /// regular files.
+    pub fn set_size(self, size: i64) -> Self {
+        unsafe {
+            archive_entry_set_size(self.entry, size);
+        }
+        self
+    }
+
+    pub fn set_uid(self, uid: i64) -> Self {
+        unsafe {
+            archive_entry_set_uid(self.entry, uid);
+        }
+        self
+    }
+
+    pub fn set_gid(self, gid: i64) -> Self {
+        unsafe {
+            archive_entry_set_gid(self.entry, gid);
+        }
+        self
+    }
+
+    /// The device of a character or block device entry.
+    pub fn set_rdev(self, major: u64, minor: u64) -> Self {
+        unsafe {
+            archive_entry_set_rdevmajor(self.entry, major as dev_t);
+            archive_entry_set_rdevminor(self.entry, minor as dev_t);
+        }
+        self
+    }
+
+    /// The device the file is on, which together with `set_ino` lets
+    /// libarchive detect hardlinks.
+    pub fn set_dev(self, major: u64, minor: u64) -> Self {
+        unsafe {
+            archive_entry_set_devmajor(self.entry, major as dev_t);
+            archive_entry_set_devminor(self.entry, minor as dev_t);
+        }
+        self
+    }
+
+    pub fn set_ino(self, ino: i64) -> Self {
+        unsafe {
+            archive_entry_set_ino64(self.entry, ino);
+        }
+        self
+    }
+
+    pub fn set_nlink(self, nlink: u32) -> Self {
+        unsafe {
+            archive_entry_set_nlink(self.entry, nlink as c_uint);
+        }
+        self
+    }
+
+    build_name!(pathname);
+    build_name!(uname);
+    build_name!(gname);
+    build_name!(symlink);
+    build_name!(hardlink);
+
+    build_time!(access_time, atime);
+    build_time!(creation_time, birthtime);
+    build_time!(inode_change_time, ctime);
+    build_time!(modification_time, mtime);
+}
+
+impl Drop for EntryBuilder {
+    fn drop(&mut self) {
+        unsafe {
+            archive_entry_free(self.entry);
+        }
+    }
+}
+
+fn alloc_entry() -> Result<EntryBuilder, ArchiveError> {
+    EntryBuilder::new().map_err(|AllocationError| ArchiveError {
+        code: ErrorCode::Fatal,
+        errno: 0,
+        message: Some("cannot allocate the entry".to_string()),
+        io_kind: Some(io::ErrorKind::OutOfMemory),
+    })
+}
+
+// an entry without data for the `Writer::add_*` shortcuts, formats like cpio
+// require the size
+fn new_entry(
+    pathname: &str,
+    filetype: ArchiveEntryFiletype,
+    perm: u32,
+) -> Result<EntryBuilder, ArchiveError> {
+    Ok(alloc_entry()?
+        .set_pathname(pathname)
+        .set_filetype(filetype)
+        .set_perm(perm)
+        .set_size(0))
+}
+
+// the path as it is, also when it is not UTF-8; Windows paths are passed
+// wide where libarchive can take them
+#[cfg(unix)]
+fn path_to_cstring(path: &Path) -> CString {
+    use std::os::unix::ffi::OsStrExt;
+    CString::new(path.as_os_str().as_bytes()).unwrap()
+}
+
+#[cfg(not(unix))]
+fn path_to_cstring(path: &Path) -> CString {
+    CString::new(path.to_string_lossy().into_owned()).unwrap()
+}
+
+#[cfg(unix)]
+unsafe fn copy_sourcepath(entry: *mut Struct_archive_entry, path: &Path) {
+    let c_path = path_to_cstring(path);
+    archive_entry_copy_sourcepath(entry, c_path.as_ptr());
+}
+
+#[cfg(windows)]
+unsafe fn copy_sourcepath(entry: *mut Struct_archive_entry, path: &Path) {
+    use std::os::windows::ffi::OsStrExt;
+    let mut wide: Vec<wchar_t> = path.as_os_str().encode_wide().collect();
+    wide.push(0);
+    archive_entry_copy_sourcepath_w(entry, wide.as_ptr());
+}
+
+// reads the metadata of files for `Writer::append_path`
+struct DiskReader {
+    handler: *mut Struct_archive,
+}
+
+impl DiskReader {
+    fn new() -> Result<DiskReader, ArchiveError> {
+        unsafe {
+            let handler = archive_read_disk_new();
+            if handler.is_null() {
+                return Err(ArchiveError {
+                    code: ErrorCode::Fatal,
+                    errno: 0,
+                    message: Some("cannot allocate the disk reader".to_string()),
+                    io_kind: Some(io::ErrorKind::OutOfMemory),
+                });
+            }
+            let disk = DiskReader { handler };
+            // user and group names besides the ids
+            let res = archive_read_disk_set_standard_lookup(handler);
+            check_result(handler, &Warnings::default(), res)?;
+            Ok(disk)
+        }
+    }
+}
+
+impl Drop for DiskReader {
+    fn drop(&mut self) {
+        unsafe {
+            archive_read_free(self.handler);
+        }
+    }
 }
@@ -0,0 +1,136 @@
+extern crate Archive;
+
+use std::io::{self, Cursor, Read};
+use Archive::*;
+
+fn small_tar() -> Vec<u8> {
+    let mut w = Writer::new()
+        .unwrap()
+        .set_format(ArchiveFormat::Ustar)
+        .open_vec()
+        .unwrap();
+    w.write_header_new("a.txt", 5).unwrap();
+    w.write_data(b"hello".to_vec()).unwrap();
+    w.close().map_err(|(err, _)| err).unwrap().1
+}
+
+// fails every read with the given error
+struct Failing(fn() -> io::Error);
+
+impl Read for Failing {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        Err((self.0)())
+    }
+}
+
+fn open_err(source: Failing) -> ArchiveError {
+    Reader::new()
+        .unwrap()
+        .support_format_all()
+        .open_stream(source)
+        .map(|_| ())
+        .unwrap_err()
+}
+
+#[test]
+#[should_panic(expected = "source panicked")]
+fn source_panic_is_resumed() {
+    struct Panicking;
+
+    impl Read for Panicking {
+        fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+            panic!("source panicked")
+        }
+    }
+
+    let _ = Reader::new()
+        .unwrap()
+        .support_format_all()
+        .open_stream(Panicking);
+}
+
+#[test]
+fn source_error_keeps_its_kind() {
+    let err = open_err(Failing(|| {
+        io::Error::new(io::ErrorKind::TimedOut, "too slow")
+    }));
+    assert_eq!(err.io_error_kind(), Some(io::ErrorKind::TimedOut));
+    assert_eq!(err.message(), Some("too slow"));
+    let err: io::Error = err.into();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+}
+
+#[test]
+fn source_error_with_nul() {
+    let err = open_err(Failing(|| {
+        io::Error::new(io::ErrorKind::Other, "bad\0data")
+    }));
+    assert_eq!(err.io_error_kind(), Some(io::ErrorKind::Other));
+    assert_eq!(err.message(), Some("bad\0data"));
+}
+
+// interrupted before every read
+struct Interrupting {
+    data: Cursor<Vec<u8>>,
+    interrupt: bool,
+}
+
+impl Read for Interrupting {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.interrupt = !self.interrupt;
+        if self.interrupt {
+            Err(io::Error::from(io::ErrorKind::Interrupted))
+        } else {
+            self.data.read(buf)
+        }
+    }
+}
+
+#[test]
+fn interrupted_source_is_read_again() {
+    let source = Interrupting {
+        data: Cursor::new(small_tar()),
+        interrupt: false,
+    };
+    let mut r = Reader::new()
+        .unwrap()
+        .support_format_all()
+        .open_stream(source)
+        .unwrap();
+    let mut entry = r.next_header().unwrap();
+    assert_eq!(entry.pathname().as_deref(), Some("a.txt"));
+    let mut content = String::new();
+    entry.read_to_string(&mut content).unwrap();
+    assert_eq!(content, "hello");
+}
+
+#[test]
+fn damaged_header_is_one_failed_entry() {
+    let mut w = Writer::new()
+        .unwrap()
+        .set_format(ArchiveFormat::Ustar)
+        .open_vec()
+        .unwrap();
+    for &(name, size) in &[("a.txt", 5), ("b.txt", 3000), ("c.txt", 5)] {
+        w.write_header_new(name, size).unwrap();
+        w.write_data(vec![b'x'; size as usize]).unwrap();
+    }
+    let mut data = w.close().map_err(|(err, _)| err).unwrap().1;
+    // the checksum of the header of b.txt, after a.txt and its data block
+    data[1024 + 148] ^= 1;
+
+    let mut r = Reader::new()
+        .unwrap()
+        .support_format_all()
+        .skip_failed_entries()
+        .open_memory(&data)
+        .unwrap();
+    let mut names = Vec::new();
+    while let Some(entry) = r.next_entry() {
+        names.push(entry.unwrap().pathname().unwrap());
+    }
+    assert_eq!(names, ["a.txt", "c.txt"]);
+    let failed = r.take_failed_entries();
+    assert_eq!(failed.len(), 1);
+    assert_eq!(failed[0].error.code(), ErrorCode::Retry);
+}
@@ -0,0 +1,202 @@
+extern crate Archive;
+
+use std::fs;
+use std::io::{self, Read, Write};
+use Archive::*;
+
+// a finished tar archive ends in two zero blocks
+fn has_trailer(data: &[u8]) -> bool {
+    data.len() >= 1024 && data[data.len() - 1024..].iter().all(|&b| b == 0)
+}
+
+#[test]
+fn abort_writes_no_trailer() {
+    let path = std::env::temp_dir().join(format!("abort-{}.tar", std::process::id()));
+    let mut w = Writer::new().unwrap().set_format(ArchiveFormat::Ustar);
+    w.open_filename(path.to_str().unwrap()).unwrap();
+    w.write_header_new("a.txt", 3).unwrap();
+    w.write_data(b"abc".to_vec()).unwrap();
+    w.abort();
+    let data = fs::read(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert!(!has_trailer(&data));
+
+    let mut w = Writer::new()
+        .unwrap()
+        .set_format(ArchiveFormat::Ustar)
+        .open_vec()
+        .unwrap();
+    w.write_header_new("a.txt", 20000).unwrap();
+    w.write_data(vec![b'x'; 20000]).unwrap();
+    let data = w.abort();
+    assert!(!has_trailer(&data));
+}
+
+struct BrokenPipe {
+    id: usize,
+}
+
+impl Write for BrokenPipe {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(io::Error::new(io::ErrorKind::BrokenPipe, "closed"))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn failed_close_gives_back_the_sink() {
+    let mut w = Writer::new()
+        .unwrap()
+        .set_format(ArchiveFormat::Ustar)
+        .open_stream(BrokenPipe { id: 7 })
+        .unwrap();
+    w.write_header_new("a.txt", 3).unwrap();
+    w.write_data(b"abc".to_vec()).unwrap();
+    let (err, sink) = match w.close() {
+        Ok(_) => panic!("the sink failed"),
+        Err(failed) => failed,
+    };
+    assert_eq!(err.io_error_kind(), Some(io::ErrorKind::BrokenPipe));
+    assert_eq!(sink.id, 7);
+}
+
+#[test]
+fn round_trip_through_memory() {
+    let mut w = Writer::new()
+        .unwrap()
+        .set_format(ArchiveFormat::Pax)
+        .open_vec()
+        .unwrap();
+    w.add_directory("dir/").unwrap();
+    let entry = EntryBuilder::new()
+        .unwrap()
+        .set_pathname("dir/link")
+        .set_filetype(ArchiveEntryFiletype::AE_IFLNK)
+        .set_perm(0o777)
+        .set_size(0)
+        .set_symlink("file.txt")
+        .set_uname("alice");
+    w.write_entry(&entry).unwrap();
+    w.write_header_new("dir/file.txt", 5).unwrap();
+    w.write_data(b"hello".to_vec()).unwrap();
+    let (stats, data) = w.close().map_err(|(err, _)| err).unwrap();
+    assert_eq!(stats.file_count, 3);
+
+    let mut r = Reader::new()
+        .unwrap()
+        .support_format_all()
+        .open_memory(&data)
+        .unwrap();
+    let mut names = Vec::new();
+    while let Some(entry) = r.next_entry() {
+        let mut entry = entry.unwrap();
+        let name = entry.pathname().unwrap();
+        match &name[..] {
+            "dir/" => assert_eq!(entry.size(), 0),
+            "dir/link" => {
+                assert_eq!(entry.symlink().as_deref(), Some("file.txt"));
+                assert_eq!(entry.uname().as_deref(), Some("alice"));
+            }
+            "dir/file.txt" => {
+                let mut content = String::new();
+                entry.read_to_string(&mut content).unwrap();
+                assert_eq!(content, "hello");
+            }
+            _ => panic!("unexpected entry {}", name),
+        }
+        names.push(name);
+    }
+    assert_eq!(names, ["dir/", "dir/link", "dir/file.txt"]);
+}
+
+#[test]
+fn fixed_buffer_too_small() {
+    let mut buffer = [0; 100];
+    let mut w = Writer::new()
+        .unwrap()
+        .set_format(ArchiveFormat::Ustar)
+        .open_memory(&mut buffer)
+        .unwrap();
+    w.write_header_new("a.txt", 3).unwrap();
+    w.write_data(b"abc".to_vec()).unwrap();
+    let err = w.close().unwrap_err();
+    assert_eq!(err.message(), Some("buffer too small"));
+    assert_eq!(err.io_error_kind(), Some(io::ErrorKind::WriteZero));
+}
+
+// writes `a` and a hardlink `b` to it, returning the result of the link
+fn write_hardlink(format: ArchiveFormat) -> (Result<(), ArchiveError>, Vec<u8>) {
+    let mut w = Writer::new()
+        .unwrap()
+        .set_format(format)
+        .open_vec()
+        .unwrap();
+    w.write_header_new("a", 2).unwrap();
+    w.write_data(b"hi".to_vec()).unwrap();
+    let linked = w.add_hardlink("b", "a").map(|_| ());
+    (linked, w.close().map_err(|(err, _)| err).unwrap().1)
+}
+
+// the pathname, hardlink and content of each entry
+fn read_entries(data: &[u8]) -> Vec<(String, Option<String>, String)> {
+    let mut r = Reader::new()
+        .unwrap()
+        .support_format_all()
+        .open_memory(data)
+        .unwrap();
+    let mut entries = Vec::new();
+    while let Some(entry) = r.next_entry() {
+        let mut entry = entry.unwrap();
+        let mut content = String::new();
+        entry.read_to_string(&mut content).unwrap();
+        entries.push((entry.pathname().unwrap(), entry.hardlink(), content));
+    }
+    entries
+}
+
+#[test]
+fn hardlinks_in_tar() {
+    for format in vec![
+        ArchiveFormat::Ustar,
+        ArchiveFormat::Pax,
+        ArchiveFormat::Gnutar,
+    ] {
+        let (linked, data) = write_hardlink(format);
+        linked.unwrap();
+        assert_eq!(
+            read_entries(&data),
+            [
+                ("a".to_string(), None, "hi".to_string()),
+                ("b".to_string(), Some("a".to_string()), String::new()),
+            ]
+        );
+    }
+}
+
+#[test]
+fn hardlinks_in_shar() {
+    let (linked, data) = write_hardlink(ArchiveFormat::Shar);
+    linked.unwrap();
+    assert!(String::from_utf8(data).unwrap().contains("ln -f a b"));
+}
+
+#[test]
+fn hardlinks_fail_without_links_by_name() {
+    for format in vec![
+        ArchiveFormat::Cpio_newc,
+        ArchiveFormat::Zip,
+        ArchiveFormat::_7Zip,
+    ] {
+        let (linked, data) = write_hardlink(format);
+        let err = linked.unwrap_err();
+        assert_eq!(err.code(), ErrorCode::Failed);
+        assert!(err.message().unwrap().contains("cannot archive hardlinks"));
+        assert_eq!(
+            read_entries(&data),
+            [("a".to_string(), None, "hi".to_string())]
+        );
+    }
+}